@@ -1,89 +1,315 @@
 use crate::{
-    get_service_address_file,
-    messages::{deserialize_from, serialize_to, AttachError, DetachError, Message},
+    get_service_socket_file,
+    messages::{
+        deserialize_from, serialize_to, AttachError, DestinationStatus, DetachError, Hello,
+        Message, ProcessStatus, PurgeError, CAPABILITY_CHILDREN, CAPABILITY_STATUS,
+        CAPABILITY_WATCH, PROTOCOL_VERSION,
+    },
+    output::{ClientError, OutputFormat, Report},
+    state_dir::check_file_owner,
+};
+use std::{
+    fmt::Write as _,
+    io::Write,
+    net::IpAddr,
+    os::unix::net::UnixStream,
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use std::{io::Write, net::TcpStream, process::Command, time::Duration};
 
-pub fn launch(command: &[String], delay: Duration) {
+pub fn launch(command: &[String], delay: Duration, children: bool, output: OutputFormat) {
     let mut command = command.iter();
     let executable = command.next().expect("Executable name");
 
-    let mut output = Command::new(executable)
-        .args(command)
-        .spawn()
-        .expect("Fail to launch process");
+    let mut report = Report::new("launch");
+    let mut process = match Command::new(executable).args(command).spawn() {
+        Ok(process) => process,
+        Err(e) => return report.finish(output, Err(ClientError::LaunchFailed(e.to_string()))),
+    };
+
+    let pid = process.id();
+    report.pids.push(pid);
+    let result = request_attach(pid, delay, children);
+    let exit_code = report.print(
+        output,
+        result.map(|_| format!("Successfuly attached to process: {pid}")),
+    );
+
+    // The process keeps running without its routes, still wait for it before failing.
+    process.wait().expect("Fail to wait for process");
+    if let Some(exit_code) = exit_code {
+        std::process::exit(exit_code);
+    }
+}
 
-    let pid = output.id();
-    attach(pid, delay);
+pub fn attach(pid: u32, delay: Duration, children: bool, output: OutputFormat) {
+    let mut report = Report::new("attach");
+    report.pids.push(pid);
 
-    output.wait().expect("Fail to wait for process");
+    let result = request_attach(pid, delay, children);
+    report.finish(
+        output,
+        result.map(|_| format!("Successfuly attached to process: {pid}")),
+    );
 }
 
-pub fn attach(pid: u32, delay: Duration) {
-    let mut stream = connect_to_service();
+pub fn detach_from_process(pid: u32, output: OutputFormat) {
+    let mut report = Report::new("detach");
+    report.pids.push(pid);
+
+    let result = request_detach(pid);
+    report.finish(output, result.map(|_| String::new()));
+}
+
+pub fn purge(output: OutputFormat) {
+    let mut report = Report::new("purge");
+
+    let result = request_purge().map(|addresses| {
+        report.addresses = addresses;
+
+        String::new()
+    });
+    report.finish(output, result);
+}
+
+pub fn status(output: OutputFormat) {
+    let mut report = Report::new("status");
+
+    let result = request_status().map(|(processes, destinations)| {
+        let message = format_status(&processes, &destinations);
+
+        report.pids = processes.iter().map(|process| process.pid).collect();
+        report.addresses = destinations
+            .iter()
+            .map(|destination| destination.address)
+            .collect();
+        report.processes = Some(processes);
+        report.destinations = Some(destinations);
+
+        message
+    });
+    report.finish(output, result);
+}
+
+pub fn watch(pid: Option<u32>, address: Option<IpAddr>, output: OutputFormat) {
+    let mut report = Report::new("watch");
+    report.pids.extend(pid);
+    report.addresses.extend(address);
+
+    let stream = match request_watch(pid, address) {
+        Ok(stream) => stream,
+        Err(e) => return report.finish(output, Err(e)),
+    };
+
+    // Print events until the service goes away.
+    loop {
+        let msg = match deserialize_from::<Message, _>(&stream) {
+            Ok(msg) => msg,
+            Err(_) => {
+                let e = ClientError::ServiceUnavailable("connection lost".to_owned());
+
+                return report.finish(output, Err(e));
+            }
+        };
+        let Message::Event { event } = msg else {
+            let e = ClientError::Protocol("unexpected message received".to_owned());
+
+            return report.finish(output, Err(e));
+        };
+
+        match output {
+            OutputFormat::Json => match serde_json::to_string(&event) {
+                Ok(json) => println!("{json}"),
+                Err(e) => eprintln!("Fail to serialize event: {e}"),
+            },
+            OutputFormat::Text => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                println!("[{now}] {event}");
+            }
+        }
+    }
+}
+
+fn request_attach(pid: u32, delay: Duration, children: bool) -> Result<(), ClientError> {
+    let (mut stream, hello) = connect_to_service()?;
+    if children && !hello.has_capability(CAPABILITY_CHILDREN) {
+        return Err(ClientError::Unsupported("track child processes"));
+    }
 
-    // Send message to service.
     let msg = Message::AttachRequest {
         pid,
         delay: delay.as_millis() as u32,
+        children,
     };
-    serialize_to(&msg, &stream).expect("Fail to send message to service");
-    stream.flush().expect("Fail to flush pipe");
-
-    // Receive response from service.
-    match deserialize_from::<Message, _>(stream).expect("Fail to read response message") {
+    match request(&mut stream, &msg)? {
         Message::AttachResponse { error } => match error {
-            AttachError::Ok => println!("Successfuly attached to process: {pid}"),
-            AttachError::ProcessNotFound => println!("Process not found."),
+            AttachError::Ok => Ok(()),
+            AttachError::ProcessNotFound => Err(ClientError::ProcessNotFound(pid)),
+            AttachError::PermissionDenied => Err(ClientError::PermissionDenied(pid)),
         },
 
-        _ => panic!("Unexpected message received!"),
+        _ => Err(ClientError::Protocol(
+            "unexpected message received".to_owned(),
+        )),
     }
 }
 
-pub fn detach_from_process(pid: u32) {
-    let mut stream = connect_to_service();
+fn request_detach(pid: u32) -> Result<(), ClientError> {
+    let (mut stream, _) = connect_to_service()?;
 
-    // Send message to service.
-    let msg = Message::DetachRequest { pid };
-    serialize_to(&msg, &stream).expect("Fail to send message to service");
-    stream.flush().expect("Fail to flush pipe");
-
-    // Receive response from service.
-    let msg: Message = deserialize_from(stream).expect("Fail to read response message");
-    match msg {
+    match request(&mut stream, &Message::DetachRequest { pid })? {
         Message::DetachResponse { error } => match error {
-            DetachError::Ok => { /* Do nothing. */ }
-            DetachError::ProcessNotFound => println!("Process not found!"),
-            DetachError::UnknownError => println!("Unknown error occured in service!"),
+            DetachError::Ok => Ok(()),
+            DetachError::ProcessNotFound => Err(ClientError::ProcessNotFound(pid)),
+            DetachError::UnknownError => Err(ClientError::ServiceError),
+            DetachError::PermissionDenied => Err(ClientError::PermissionDenied(pid)),
+        },
+
+        _ => Err(ClientError::Protocol(
+            "unexpected message received".to_owned(),
+        )),
+    }
+}
+
+fn request_purge() -> Result<Vec<IpAddr>, ClientError> {
+    let (mut stream, _) = connect_to_service()?;
+
+    match request(&mut stream, &Message::PurgeRequest)? {
+        Message::PurgeResponse { error, addresses } => match error {
+            PurgeError::Ok => Ok(addresses),
+            PurgeError::UnknownError => Err(ClientError::ServiceError),
+            PurgeError::PermissionDenied => Err(ClientError::AdminOnly("purge connections")),
         },
 
-        _ => panic!("Unexpected message received!"),
+        _ => Err(ClientError::Protocol(
+            "unexpected message received".to_owned(),
+        )),
     }
 }
 
-pub fn purge() {
-    let mut stream = connect_to_service();
+fn request_status() -> Result<(Vec<ProcessStatus>, Vec<DestinationStatus>), ClientError> {
+    let (mut stream, hello) = connect_to_service()?;
+    if !hello.has_capability(CAPABILITY_STATUS) {
+        return Err(ClientError::Unsupported("report its status"));
+    }
 
-    // Send message to service.
-    let msg = Message::PurgeRequest;
-    serialize_to(&msg, &stream).expect("Fail to send message to service");
-    stream.flush().expect("Fail to flush pipe");
+    match request(&mut stream, &Message::StatusRequest)? {
+        Message::StatusResponse {
+            processes,
+            destinations,
+        } => Ok((processes, destinations)),
 
-    // Receive response from service.
-    let msg: Message = deserialize_from(stream).expect("Fail to read response message");
-    if msg != Message::PurgeResponse {
-        println!("Fail to purge connections!");
+        _ => Err(ClientError::Protocol(
+            "unexpected message received".to_owned(),
+        )),
     }
 }
 
-fn connect_to_service() -> TcpStream {
-    // Find port of the service to connect to.
-    let port_file_name = get_service_address_file();
-    let port = std::fs::read_to_string(port_file_name).unwrap();
-    let port = port.parse::<u16>().unwrap();
-    let service_address = format!("127.0.0.1:{port}");
+fn request_watch(pid: Option<u32>, address: Option<IpAddr>) -> Result<UnixStream, ClientError> {
+    let (mut stream, hello) = connect_to_service()?;
+    if !hello.has_capability(CAPABILITY_WATCH) {
+        return Err(ClientError::Unsupported("stream events"));
+    }
+
+    serialize_to(&Message::WatchRequest { pid, address }, &stream)?;
+    stream
+        .flush()
+        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+
+    Ok(stream)
+}
+
+fn format_status(processes: &[ProcessStatus], destinations: &[DestinationStatus]) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut message = String::new();
+    writeln!(message, "Attached processes: {}", processes.len()).unwrap_or_default();
+    for process in processes {
+        writeln!(
+            message,
+            "  {:>7}  attached {}s ago, delay {} ms, children {}: {}",
+            process.pid,
+            now.saturating_sub(process.attach_time),
+            process.delay,
+            match (process.children, process.tracked_children.as_slice()) {
+                (false, _) => "ignored".to_string(),
+                (true, []) => "tracked".to_string(),
+                (true, pids) => format!(
+                    "tracked ({})",
+                    pids.iter()
+                        .map(|pid| pid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            },
+            process.command_line
+        )
+        .unwrap_or_default();
+    }
+
+    write!(message, "Destinations: {}", destinations.len()).unwrap_or_default();
+    for destination in destinations {
+        let duration = destination
+            .state_duration
+            .map(|duration| format!(" for {}s", duration / 1000))
+            .unwrap_or_default();
+        let pid = destination
+            .pid
+            .map(|pid| format!(", triggered by PID {pid}"))
+            .unwrap_or_default();
+
+        write!(
+            message,
+            "\n  {:<39}  {}{duration}{pid}",
+            destination.address.to_string(),
+            destination.state
+        )
+        .unwrap_or_default();
+    }
+
+    message
+}
+
+/// Sends the request and waits for the response.
+fn request(stream: &mut UnixStream, msg: &Message) -> Result<Message, ClientError> {
+    serialize_to(msg, &*stream)?;
+    stream
+        .flush()
+        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+
+    Ok(deserialize_from(&*stream)?)
+}
+
+fn connect_to_service() -> Result<(UnixStream, Hello), ClientError> {
+    // Find socket of the service to connect to.
+    let socket_file_name = get_service_socket_file();
+    if !socket_file_name.exists() {
+        let e = format!("{} not found", socket_file_name.display());
+
+        return Err(ClientError::ServiceUnavailable(e));
+    }
+    check_file_owner(&socket_file_name)
+        .map_err(|e| ClientError::UntrustedService(e.to_string()))?;
 
     // Connect to service.
-    TcpStream::connect(service_address).unwrap()
+    let mut stream = UnixStream::connect(socket_file_name)
+        .map_err(|e| ClientError::ServiceUnavailable(e.to_string()))?;
+
+    // Make sure both sides speak the same protocol.
+    serialize_to(&Hello::current(), &stream)?;
+    stream
+        .flush()
+        .map_err(|e| ClientError::Protocol(e.to_string()))?;
+    let hello: Hello = deserialize_from(&stream)?;
+    if hello.version != PROTOCOL_VERSION {
+        return Err(ClientError::IncompatibleVersion(hello.version));
+    }
+
+    Ok((stream, hello))
 }
@@ -1,21 +1,84 @@
-use std::{cmp::Ordering, net::Ipv4Addr, time::Instant};
+use super::state_file::{from_unix_time, to_unix_time, ConnectionRecord, StateRecord};
+use std::{
+    cmp::Ordering,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Precision of the last use saved in the state file.
+const LAST_SEEN_STEP: Duration = Duration::from_secs(60);
 
 pub enum ConnectionState {
-    Pending { start_time: Instant },
-    InRoutingTable,
+    Pending {
+        start_time: Instant,
+    },
+    InRoutingTable {
+        start_time: Instant,
+    },
+    /// A connection to the address was established through the route.
+    Verified,
+    /// The route didn't help establishing a connection and was removed, it is retried after a
+    /// backoff.
+    Failed {
+        start_time: Instant,
+    },
+    /// Removed from the routing table while the VPN is down.
+    Suspended,
+}
+
+impl ConnectionState {
+    pub fn is_in_routing_table(&self) -> bool {
+        matches!(
+            self,
+            ConnectionState::InRoutingTable { .. } | ConnectionState::Verified
+        )
+    }
+}
+
+/// Outcome of connecting to the address through the physical interface.
+#[derive(PartialEq)]
+pub enum ProbeResult {
+    Reachable,
+    Unreachable(String),
 }
 
 pub struct Connection {
-    address: Ipv4Addr,
+    address: IpAddr,
     state: ConnectionState,
+    /// Attached process whose connection triggered the address, if known.
+    pid: Option<u32>,
+    executable: Option<PathBuf>,
+    gateway: Option<IpAddr>,
+    remote_ports: Vec<u16>,
+    probe: Option<ProbeResult>,
+    /// Number of times in a row the route didn't help.
+    failures: u32,
+    first_seen: SystemTime,
+    /// Last time a tracked process had a socket to the address.
+    last_seen: SystemTime,
+    /// A field saved in the state file changed since the last save.
+    dirty: bool,
 }
 
 impl Connection {
-    pub fn new(address: Ipv4Addr, state: ConnectionState) -> Self {
-        Self { address, state }
+    pub fn new(address: IpAddr, state: ConnectionState, pid: Option<u32>) -> Self {
+        Self {
+            address,
+            state,
+            pid,
+            executable: None,
+            gateway: None,
+            remote_ports: Vec::new(),
+            probe: None,
+            failures: 0,
+            first_seen: SystemTime::now(),
+            last_seen: SystemTime::now(),
+            dirty: true,
+        }
     }
 
-    pub fn address(&self) -> &Ipv4Addr {
+    pub fn address(&self) -> &IpAddr {
         &self.address
     }
 
@@ -24,8 +87,144 @@ impl Connection {
     }
 
     pub fn set_state(&mut self, value: ConnectionState) {
+        // Start times aren't saved, only the kind of state is.
+        if StateRecord::from(&value) != StateRecord::from(&self.state) {
+            self.dirty = true;
+        }
+        match value {
+            ConnectionState::Failed { .. } => self.failures += 1,
+            ConnectionState::Verified => self.failures = 0,
+            _ => { /* Do nothing. */ }
+        }
+
         self.state = value;
     }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    pub fn set_executable(&mut self, value: Option<PathBuf>) {
+        if value != self.executable {
+            self.dirty = true;
+        }
+
+        self.executable = value;
+    }
+
+    pub fn set_gateway(&mut self, value: Option<IpAddr>) {
+        if value != self.gateway {
+            self.dirty = true;
+        }
+
+        self.gateway = value;
+    }
+
+    pub fn remote_ports(&self) -> &[u16] {
+        &self.remote_ports
+    }
+
+    /// Returns whether the port wasn't known yet.
+    pub fn add_remote_port(&mut self, port: u16) -> bool {
+        let Err(index) = self.remote_ports.binary_search(&port) else {
+            return false;
+        };
+        self.remote_ports.insert(index, port);
+        self.dirty = true;
+
+        true
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn probe(&self) -> Option<&ProbeResult> {
+        self.probe.as_ref()
+    }
+
+    pub fn set_probe(&mut self, value: ProbeResult) {
+        self.probe = Some(value);
+    }
+
+    pub fn last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    pub fn touch(&mut self) {
+        let now = SystemTime::now();
+
+        // Only rewrite the state file once per step while the address keeps being used.
+        let step = LAST_SEEN_STEP.as_secs();
+        if to_unix_time(now) / step != to_unix_time(self.last_seen) / step {
+            self.dirty = true;
+        }
+
+        self.last_seen = now;
+    }
+
+    pub(super) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub(super) fn set_saved(&mut self) {
+        self.dirty = false;
+    }
+}
+
+impl From<&ConnectionState> for StateRecord {
+    fn from(state: &ConnectionState) -> Self {
+        match state {
+            ConnectionState::Pending { .. } => StateRecord::Pending,
+            ConnectionState::InRoutingTable { .. } => StateRecord::InRoutingTable,
+            ConnectionState::Verified => StateRecord::Verified,
+            ConnectionState::Failed { .. } => StateRecord::Failed,
+            ConnectionState::Suspended => StateRecord::Suspended,
+        }
+    }
+}
+
+impl From<&Connection> for ConnectionRecord {
+    fn from(connection: &Connection) -> Self {
+        ConnectionRecord {
+            address: connection.address,
+            state: StateRecord::from(&connection.state),
+            gateway: connection.gateway,
+            first_seen: to_unix_time(connection.first_seen),
+            last_seen: to_unix_time(connection.last_seen),
+            pid: connection.pid,
+            executable: connection.executable.clone(),
+            remote_ports: connection.remote_ports.clone(),
+        }
+    }
+}
+
+impl From<ConnectionRecord> for Connection {
+    /// Routes are reconciled with the routing table at startup, timers restart from now.
+    fn from(record: ConnectionRecord) -> Self {
+        let start_time = Instant::now();
+        let state = match record.state {
+            StateRecord::Pending => ConnectionState::Pending { start_time },
+            StateRecord::InRoutingTable => ConnectionState::InRoutingTable { start_time },
+            StateRecord::Verified => ConnectionState::Verified,
+            StateRecord::Failed => ConnectionState::Failed { start_time },
+            StateRecord::Suspended => ConnectionState::Suspended,
+        };
+
+        Connection {
+            address: record.address,
+            state,
+            pid: record.pid,
+            executable: record.executable,
+            gateway: record.gateway,
+            remote_ports: record.remote_ports,
+            probe: None,
+            failures: u32::from(record.state == StateRecord::Failed),
+            first_seen: from_unix_time(record.first_seen),
+            last_seen: from_unix_time(record.last_seen),
+            dirty: false,
+        }
+    }
 }
 
 impl PartialEq for Connection {
@@ -38,7 +237,7 @@ impl Eq for Connection {}
 
 impl PartialOrd for Connection {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.address.cmp(&other.address))
+        Some(self.cmp(other))
     }
 }
 
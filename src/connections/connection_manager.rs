@@ -1,9 +1,11 @@
-use super::{Connection, ConnectionState};
+use super::{
+    state_file::{load_connection_file, load_state, save_state},
+    Connection, ConnectionState,
+};
+use crate::state_dir::{check_file_owner, get_state_dir};
 use std::{
-    io::Write,
-    net::Ipv4Addr,
-    path::PathBuf,
-    str::FromStr,
+    net::IpAddr,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex, OnceLock},
     time::Instant,
 };
@@ -13,52 +15,36 @@ static CONNECTION_MANAGER: OnceLock<Arc<Mutex<ConnectionManager>>> = OnceLock::n
 #[derive(Default)]
 pub struct ConnectionManager {
     connections: Vec<Connection>,
+    /// Connections were added or removed since the last save.
+    dirty: bool,
 }
 
 impl ConnectionManager {
     pub fn add_connection(&mut self, connection: Connection) {
-        // Save connections to file.
-        let connection_file = get_connection_file_path();
-        let mut file = match std::fs::OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(connection_file)
-        {
-            Ok(file) => file,
-            Err(e) => {
-                log::error!("Fail to open connection file: {e}");
-
-                return;
-            }
-        };
-
-        writeln!(file, "{}", connection.address().to_string()).unwrap_or_default();
-
         // Register connection.
         let Err(index) = self.connections.binary_search(&connection) else {
-            log::warn!(
-                "Connection already present: {}",
-                connection.address().to_string()
-            );
+            log::warn!("Connection already present: {}", connection.address());
 
             return;
         };
         self.connections.insert(index, connection);
+        self.dirty = true;
     }
 
-    // pub fn remove_connection(&mut self, address: &Ipv4Addr) -> Option<Connection> {
-    //     // TODO: Remove from network routing table.
-    //     let Ok(index) = self
-    //         .connections
-    //         .binary_search_by(|connection| connection.address().cmp(address))
-    //     else {
-    //         return None;
-    //     };
-    //
-    //     Some(self.connections.remove(index))
-    // }
-    //
-    // pub fn get_connection(&self, address: &Ipv4Addr) -> Option<&Connection> {
+    pub fn remove_connection(&mut self, address: &IpAddr) -> Option<Connection> {
+        let Ok(index) = self
+            .connections
+            .binary_search_by(|connection| connection.address().cmp(address))
+        else {
+            return None;
+        };
+        let connection = self.connections.remove(index);
+        self.dirty = true;
+
+        Some(connection)
+    }
+
+    // pub fn get_connection(&self, address: &IpAddr) -> Option<&Connection> {
     //     let Ok(index) = self
     //         .connections
     //         .binary_search_by(|connection| connection.address().cmp(address))
@@ -69,7 +55,7 @@ impl ConnectionManager {
     //     self.connections.get(index)
     // }
 
-    pub fn get_connection_mut(&mut self, address: &Ipv4Addr) -> Option<&mut Connection> {
+    pub fn get_connection_mut(&mut self, address: &IpAddr) -> Option<&mut Connection> {
         let Ok(index) = self
             .connections
             .binary_search_by(|connection| connection.address().cmp(address))
@@ -83,8 +69,7 @@ impl ConnectionManager {
     pub fn purge(&mut self) {
         self.connections.clear();
 
-        let connection_file = get_connection_file_path();
-        std::fs::remove_file(connection_file).unwrap_or_default();
+        self.save();
     }
 
     pub fn iter(&self) -> std::slice::Iter<'_, Connection> {
@@ -94,42 +79,164 @@ impl ConnectionManager {
     pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Connection> {
         self.connections.iter_mut()
     }
+
+    /// Saves the connections if they changed since the last save.
+    pub fn flush(&mut self) {
+        if self.dirty || self.connections.iter().any(Connection::is_dirty) {
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        let state_file = get_state_file_path();
+        if let Err(e) = save_state(&state_file, &self.connections) {
+            log::error!("Fail to save state file: {e}");
+
+            return;
+        }
+
+        self.dirty = false;
+        for connection in self.connections.iter_mut() {
+            connection.set_saved();
+        }
+    }
 }
 
 pub fn get_connection_mananger() -> Arc<Mutex<ConnectionManager>> {
     CONNECTION_MANAGER
         .get_or_init(|| {
-            let start_time = Instant::now();
-
-            let connection_file = get_connection_file_path();
-            let connections = std::fs::read_to_string(&connection_file).unwrap_or_default();
-            let connections: Vec<_> = connections
-                .split_whitespace()
-                .filter_map(|line| {
-                    let Ok(address) = Ipv4Addr::from_str(line) else {
-                        return None;
-                    };
-
-                    Some(Connection::new(
-                        address,
-                        ConnectionState::Pending { start_time },
-                    ))
-                })
-                .collect();
+            let state_file = get_state_file_path();
+            let mut connection_manager = ConnectionManager::default();
+
+            if state_file.exists() {
+                match load_state(&state_file) {
+                    Ok(connections) => connection_manager.connections = connections,
+                    Err(e) => {
+                        // Keep the file around instead of overwriting it.
+                        log::error!("Fail to load state file: {e}");
+                        std::fs::rename(&state_file, state_file.with_extension("json.bak"))
+                            .unwrap_or_default();
+                    }
+                }
+            } else {
+                let connection_file = std::env::temp_dir()
+                    .join(env!("CARGO_PKG_NAME"))
+                    .join("connections.txt");
+                migrate_connection_file(&mut connection_manager, &connection_file);
+            }
+
+            // Keep the connections sorted and unique, whatever the file contains.
+            let connections = &mut connection_manager.connections;
+            connections.sort();
+            connections.dedup();
             log::info!(
                 "Loaded {} addresses from: {}",
                 connections.len(),
-                connection_file.to_string_lossy()
+                state_file.to_string_lossy()
             );
 
-            Arc::new(Mutex::new(ConnectionManager { connections }))
+            Arc::new(Mutex::new(connection_manager))
         })
         .clone()
 }
 
-fn get_connection_file_path() -> PathBuf {
-    let path = std::env::temp_dir().join(env!("CARGO_PKG_NAME"));
-    std::fs::create_dir_all(&path).unwrap_or_default();
+/// Moves the addresses of the old `connections.txt` file, kept in the temporary directory, to the
+/// state file.
+fn migrate_connection_file(connection_manager: &mut ConnectionManager, connection_file: &Path) {
+    if !connection_file.exists() {
+        return;
+    }
+
+    // The temporary directory is writable by everyone, don't escape addresses planted there.
+    let owners = connection_file
+        .parent()
+        .map(check_file_owner)
+        .unwrap_or(Ok(()))
+        .and_then(|_| check_file_owner(connection_file));
+    if let Err(e) = owners {
+        log::warn!("Ignoring connection file: {e}");
 
-    path.join("connections.txt")
+        return;
+    }
+
+    let addresses = match load_connection_file(connection_file) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            log::error!("Fail to read connection file: {e}");
+
+            return;
+        }
+    };
+
+    let start_time = Instant::now();
+    connection_manager.connections = addresses
+        .into_iter()
+        .map(|address| Connection::new(address, ConnectionState::Pending { start_time }, None))
+        .collect();
+    connection_manager.connections.sort();
+    connection_manager.connections.dedup();
+
+    // Only drop the old file once the state file is saved.
+    connection_manager.dirty = true;
+    connection_manager.flush();
+    if connection_manager.dirty {
+        return;
+    }
+    std::fs::remove_file(connection_file).unwrap_or_default();
+
+    log::info!(
+        "Migrated {} addresses from: {}",
+        connection_manager.connections.len(),
+        connection_file.to_string_lossy()
+    );
+}
+
+fn get_state_file_path() -> PathBuf {
+    get_state_dir().join("state.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_dir::set_state_dir;
+
+    #[test]
+    fn migrates_connection_file() {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-test-{}-migration",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        set_state_dir(dir.clone());
+        let connection_file = dir.join("connections.txt");
+        std::fs::write(&connection_file, "203.0.113.9\n198.51.100.3\n203.0.113.9\n").unwrap();
+
+        let mut connection_manager = ConnectionManager::default();
+        migrate_connection_file(&mut connection_manager, &connection_file);
+
+        // Migrated addresses wait for a process trying them, sorted and unique.
+        let addresses: Vec<_> = connection_manager
+            .iter()
+            .map(|connection| *connection.address())
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                IpAddr::from([198, 51, 100, 3]),
+                IpAddr::from([203, 0, 113, 9])
+            ]
+        );
+        for connection in connection_manager.iter() {
+            assert!(matches!(
+                connection.state(),
+                ConnectionState::Pending { .. }
+            ));
+            assert_eq!(connection.pid(), None);
+        }
+        assert!(!connection_file.exists());
+        assert_eq!(load_state(&get_state_file_path()).unwrap().len(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,5 +1,6 @@
 mod connection;
 mod connection_manager;
+mod state_file;
 
-pub use connection::{Connection, ConnectionState};
+pub use connection::{Connection, ConnectionState, ProbeResult};
 pub use connection_manager::get_connection_mananger;
@@ -0,0 +1,229 @@
+use super::Connection;
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions, Permissions},
+    io::Write,
+    net::IpAddr,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Layout version of the state file, increased on every incompatible change.
+const STATE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    connections: Vec<ConnectionRecord>,
+}
+
+/// Only used to check the version before parsing the whole file.
+#[derive(Deserialize)]
+struct StateFileHeader {
+    version: u32,
+}
+
+/// Connection as stored in the state file.
+#[derive(Serialize, Deserialize)]
+pub struct ConnectionRecord {
+    pub(super) address: IpAddr,
+    pub(super) state: StateRecord,
+    pub(super) gateway: Option<IpAddr>,
+    /// Seconds since the Unix epoch.
+    pub(super) first_seen: u64,
+    /// Seconds since the Unix epoch.
+    pub(super) last_seen: u64,
+    pub(super) pid: Option<u32>,
+    pub(super) executable: Option<PathBuf>,
+    pub(super) remote_ports: Vec<u16>,
+}
+
+#[derive(PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateRecord {
+    Pending,
+    InRoutingTable,
+    Verified,
+    Failed,
+    Suspended,
+}
+
+/// Reads the connections saved in the state file.
+pub fn load_state(path: &Path) -> Result<Vec<Connection>> {
+    let content = std::fs::read_to_string(path)?;
+
+    let header: StateFileHeader = serde_json::from_str(&content)?;
+    if header.version > STATE_VERSION {
+        return Err(eyre!("unsupported state file version {}", header.version));
+    }
+
+    let state: StateFile = serde_json::from_str(&content)?;
+
+    Ok(state
+        .connections
+        .into_iter()
+        .map(Connection::from)
+        .collect())
+}
+
+/// Replaces the state file with the given connections.
+///
+/// The file is written next to the old one and renamed over it, so it is never left half written.
+pub fn save_state(path: &Path, connections: &[Connection]) -> Result<()> {
+    let state = StateFile {
+        version: STATE_VERSION,
+        connections: connections.iter().map(ConnectionRecord::from).collect(),
+    };
+    let content = serde_json::to_string_pretty(&state)?;
+
+    // Only the service can read the connections and the processes behind them.
+    let temp_path = path.with_extension("json.tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_path)?;
+    // The mode only applies when creating the file, not to one left by an interrupted save.
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+
+    // Make the rename itself survive a crash.
+    if let Some(directory) = path.parent() {
+        File::open(directory)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Reads the addresses of the old `connections.txt` file, one per line.
+pub fn load_connection_file(path: &Path) -> Result<Vec<IpAddr>> {
+    let content = std::fs::read_to_string(path)?;
+
+    Ok(content
+        .split_whitespace()
+        .filter_map(|line| IpAddr::from_str(line).ok())
+        .collect())
+}
+
+pub(super) fn to_unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub(super) fn from_unix_time(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::ConnectionState;
+    use std::{os::unix::fs::MetadataExt, time::Instant};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "{}-test-{}-{name}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+
+        path
+    }
+
+    fn records(connections: &[Connection]) -> serde_json::Value {
+        let records: Vec<_> = connections.iter().map(ConnectionRecord::from).collect();
+
+        serde_json::to_value(records).unwrap()
+    }
+
+    #[test]
+    fn round_trips_state() {
+        let start_time = Instant::now();
+        let mut routed = Connection::new(
+            IpAddr::from([203, 0, 113, 9]),
+            ConnectionState::InRoutingTable { start_time },
+            Some(42),
+        );
+        routed.set_gateway(Some(IpAddr::from([192, 0, 2, 1])));
+        routed.set_executable(Some(PathBuf::from("/usr/bin/curl")));
+        routed.add_remote_port(443);
+        routed.add_remote_port(80);
+        let failed = Connection::new(
+            "2001:db8::5".parse().unwrap(),
+            ConnectionState::Failed { start_time },
+            None,
+        );
+        let connections = [routed, failed];
+
+        let path = test_dir("round-trip").join("state.json");
+        save_state(&path, &connections).unwrap();
+        let loaded = load_state(&path).unwrap();
+
+        assert_eq!(records(&loaded), records(&connections));
+        assert!(matches!(
+            loaded[0].state(),
+            ConnectionState::InRoutingTable { .. }
+        ));
+        assert_eq!(loaded[0].remote_ports(), &[80, 443]);
+        assert_eq!(loaded[1].failures(), 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn resets_mode_of_leftover_temporary_file() {
+        let path = test_dir("leftover").join("state.json");
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, "half written").unwrap();
+        std::fs::set_permissions(&temp_path, Permissions::from_mode(0o644)).unwrap();
+
+        save_state(&path, &[]).unwrap();
+
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+        assert!(load_state(&path).unwrap().is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let path = test_dir("version").join("state.json");
+        std::fs::write(
+            &path,
+            r#"{"version": 99, "connections": [], "extra": true}"#,
+        )
+        .unwrap();
+
+        assert!(load_state(&path).is_err());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reads_legacy_connection_file() {
+        let path = test_dir("legacy").join("connections.txt");
+        std::fs::write(&path, "203.0.113.9\n\nnot an address\n2001:db8::5\n").unwrap();
+
+        let addresses = load_connection_file(&path).unwrap();
+
+        assert_eq!(
+            addresses,
+            [
+                IpAddr::from([203, 0, 113, 9]),
+                "2001:db8::5".parse().unwrap()
+            ]
+        );
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    ffi::CString,
+    fs::Permissions,
+    io,
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::Path,
+};
+
+/// Credentials of the process on the other side of a Unix socket.
+#[derive(Clone, Copy, Debug)]
+pub struct PeerCredentials {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Ownership and permissions of the control socket.
+pub struct SocketSettings {
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub mode: u32,
+}
+
+/// Listens on the Unix socket, replacing a stale one left by a previous run.
+///
+/// Fails when another service is still listening on it.
+pub fn bind_control_socket(path: &Path, settings: &SocketSettings) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(eyre!(
+            "Another service is already listening on {}",
+            path.display()
+        ));
+    }
+
+    match std::fs::remove_file(path) {
+        Ok(_) => { /* Do nothing. */ }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => { /* Do nothing. */ }
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    let uid = settings.owner.as_deref().map(lookup_user).transpose()?;
+    let gid = settings.group.as_deref().map(lookup_group).transpose()?;
+    std::os::unix::fs::chown(path, uid, gid)?;
+    std::fs::set_permissions(path, Permissions::from_mode(settings.mode))?;
+
+    Ok(listener)
+}
+
+/// Reads the credentials of the peer with `SO_PEERCRED`.
+pub fn get_peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(PeerCredentials {
+        pid: credentials.pid as u32,
+        uid: credentials.uid,
+        gid: credentials.gid,
+    })
+}
+
+/// Returns whether the client closed its side of the connection, without waiting.
+pub fn is_peer_gone(stream: &UnixStream) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: libc::POLLRDHUP,
+        revents: 0,
+    };
+
+    let result = unsafe { libc::poll(&mut poll_fd, 1, 0) };
+    if result < 0 {
+        return true;
+    }
+
+    poll_fd.revents & (libc::POLLRDHUP | libc::POLLHUP | libc::POLLERR) != 0
+}
+
+/// Finds the ID of a user given by name or ID.
+pub fn lookup_user(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+
+    let name = CString::new(user)?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(eyre!("unknown user {user}"));
+    }
+
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+/// Finds the ID of a group given by name or ID.
+pub fn lookup_group(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+
+    let name = CString::new(group)?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(eyre!("unknown group {group}"));
+    }
+
+    Ok(unsafe { (*entry).gr_gid })
+}
@@ -0,0 +1,33 @@
+use crate::messages::Event;
+use color_eyre::eyre::{eyre, Result};
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Mutex, OnceLock,
+};
+
+static SUBSCRIBERS: OnceLock<Mutex<Vec<Sender<Event>>>> = OnceLock::new();
+
+/// Registers a new subscriber, receiving every event published from now on.
+pub fn subscribe() -> Result<Receiver<Event>> {
+    let subscribers = SUBSCRIBERS.get_or_init(Default::default);
+    let Ok(mut subscribers) = subscribers.lock() else {
+        return Err(eyre!("Fail to lock subscribers collection."));
+    };
+
+    let (sender, receiver) = channel();
+    subscribers.push(sender);
+
+    Ok(receiver)
+}
+
+/// Sends the event to every subscriber, forgetting the ones that went away.
+pub fn publish(event: Event) {
+    let subscribers = SUBSCRIBERS.get_or_init(Default::default);
+    let Ok(mut subscribers) = subscribers.lock() else {
+        log::error!("Fail to lock subscribers collection.");
+
+        return;
+    };
+
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
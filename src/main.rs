@@ -1,21 +1,58 @@
 mod client;
 mod connections;
+mod control_socket;
+mod events;
 mod messages;
 mod monitoring;
+mod netlink;
+mod output;
 mod process_manager;
+mod routing;
 mod service;
+mod state_dir;
 
 use clap::{Parser, Subcommand};
-use client::{attach, detach_from_process, launch, purge};
-use service::service;
+use client::{attach, detach_from_process, launch, purge, status, watch};
+use control_socket::{lookup_group, SocketSettings};
+use monitoring::{create_connection_source, ConnectionSourceKind};
+use output::OutputFormat;
+use routing::{
+    create_route_backend, GatewaySetting, Gateways, RouteBackendKind, RoutePlacement,
+    VpnInterfaces, VpnLayout, MAIN_TABLE,
+};
+use service::{service, RouteTarget, ServiceConfig};
 use simple_logger::SimpleLogger;
-use std::{path::PathBuf, time::Duration};
+use state_dir::{
+    find_state_dir, get_default_state_dir, get_state_dir, prepare_state_dir, set_state_dir,
+};
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, RwLock},
+    time::Duration,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Directory of the socket and state files. The service uses /var/lib/escape-vpn when running as root and $XDG_RUNTIME_DIR/escape-vpn otherwise, clients look in both."
+    )]
+    state_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Format of the results printed by client commands."
+    )]
+    output: OutputFormat,
 }
 
 #[derive(Subcommand)]
@@ -32,6 +69,13 @@ enum Commands {
             help = "Number of milisenconds that a connection must be waiting before is added to the routing table."
         )]
         delay: u32,
+
+        #[arg(
+            short,
+            long,
+            help = "Also track every descendant of the process, including the ones spawned later."
+        )]
+        children: bool,
     },
 
     #[command(about = "Attach to a running process")]
@@ -46,6 +90,13 @@ enum Commands {
             help = "Number of milisenconds that a connection must be waiting before is added to the routing table."
         )]
         delay: u32,
+
+        #[arg(
+            short,
+            long,
+            help = "Also track every descendant of the process, including the ones spawned later."
+        )]
+        children: bool,
     },
 
     #[command(about = "Detach to a running process")]
@@ -54,16 +105,42 @@ enum Commands {
         pid: u32,
     },
 
-    #[command(about = "Remove all connections from the routing table and caching.")]
+    #[command(
+        about = "Remove all connections from the routing table and caching. Only root and the admin group can purge."
+    )]
     Purge,
 
+    #[command(about = "Show attached processes and escaped destinations")]
+    Status,
+
+    #[command(about = "Print events as they happen")]
+    Watch {
+        #[arg(long, help = "Only show events of this process.")]
+        pid: Option<u32>,
+
+        #[arg(long, help = "Only show events of this destination.")]
+        address: Option<IpAddr>,
+    },
+
     #[command(about = "Launch application as a service")]
     Service {
-        #[arg(default_value = "127.0.0.1:3131", help = "Listening port.")]
-        address: String,
+        #[arg(
+            default_value = "auto",
+            help = "Gateway IP address, optionally followed by %device, or \"auto\" to use the default route that doesn't go through a VPN."
+        )]
+        gateway: GatewaySetting,
+
+        #[arg(
+            long,
+            help = "IPv6 gateway address or \"auto\", link-local addresses need a device as in fe80::1%eth0. IPv6 destinations are ignored when not set."
+        )]
+        gateway6: Option<GatewaySetting>,
 
-        #[arg(default_value = "192.168.1.1", help = "Gateway IP address.")]
-        gateway: String,
+        #[arg(
+            long = "vpn-interface",
+            help = "Name of a VPN interface, besides the ones starting with tun, wg or ppp."
+        )]
+        vpn_interfaces: Vec<String>,
 
         #[arg(
             short,
@@ -72,6 +149,82 @@ enum Commands {
             help = "Number of milisenconds between connection check."
         )]
         pooling_rate: u32,
+
+        #[arg(
+            long,
+            help = "Number of milisenconds given to a connection to be established once its address is added to the routing table. The route is removed when it isn't. Routes aren't verified when not set."
+        )]
+        verify_window: Option<u32>,
+
+        #[arg(
+            long,
+            help = "Number of milisenconds given to a probe connection through the physical interface before the address is added to the routing table. Addresses aren't probed when not set."
+        )]
+        probe_timeout: Option<u32>,
+
+        #[arg(
+            long,
+            help = "Number of minutes after which an address that no tracked process uses is removed from the routing table. Addresses never expire when not set."
+        )]
+        lease: Option<u32>,
+
+        #[arg(long = "pin", help = "Address that never expires.")]
+        pinned: Vec<IpAddr>,
+
+        #[arg(
+            long,
+            help = "Remove the addresses that only a process used from the routing table once it exits or is detached."
+        )]
+        drop_routes: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = ConnectionSourceKind::Auto,
+            help = "Backend used to discover connections."
+        )]
+        connection_source: ConnectionSourceKind,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = RouteBackendKind::Netlink,
+            help = "Backend used to change the routing table."
+        )]
+        route_backend: RouteBackendKind,
+
+        #[arg(
+            long,
+            help = "Dedicated routing table for escaped destinations, selected by a policy rule. The main table is used when not set."
+        )]
+        table: Option<u32>,
+
+        #[arg(
+            long,
+            default_value_t = 1000,
+            help = "Priority of the policy rule that selects the dedicated routing table."
+        )]
+        rule_priority: u32,
+
+        #[arg(long, help = "Owner of the control socket, by name or ID.")]
+        socket_owner: Option<String>,
+
+        #[arg(long, help = "Group of the control socket, by name or ID.")]
+        socket_group: Option<String>,
+
+        #[arg(
+            long,
+            default_value = "660",
+            value_parser = parse_mode,
+            help = "Octal permissions of the control socket."
+        )]
+        socket_mode: u32,
+
+        #[arg(
+            long,
+            help = "Group, by name or ID, whose members can attach to and detach from processes of other users, and purge connections."
+        )]
+        admin_group: Option<String>,
     },
 }
 
@@ -82,35 +235,132 @@ fn main() {
 
     let cli = Cli::parse();
 
+    let exe_name = env!("CARGO_PKG_NAME");
+    let state_dir = match (cli.state_dir, &cli.command) {
+        (Some(state_dir), _) => state_dir,
+        (None, Commands::Service { .. }) => get_default_state_dir(),
+        (None, _) => find_state_dir(&format!("{exe_name}.sock")),
+    };
+    if let Commands::Service { .. } = cli.command {
+        prepare_state_dir(&state_dir).expect("Fail to prepare state directory");
+    }
+    set_state_dir(state_dir);
+
     match cli.command {
-        Commands::Launch { command, delay } => {
+        Commands::Launch {
+            command,
+            delay,
+            children,
+        } => {
             let delay = Duration::from_millis(delay as u64);
 
-            launch(&command, delay);
+            launch(&command, delay, children, cli.output);
         }
-        Commands::Attach { pid, delay } => {
+        Commands::Attach {
+            pid,
+            delay,
+            children,
+        } => {
             let delay = Duration::from_millis(delay as u64);
 
-            attach(pid, delay);
+            attach(pid, delay, children, cli.output);
         }
-        Commands::Detach { pid } => detach_from_process(pid),
-        Commands::Purge => purge(),
+        Commands::Detach { pid } => detach_from_process(pid, cli.output),
+        Commands::Purge => purge(cli.output),
+        Commands::Status => status(cli.output),
+        Commands::Watch { pid, address } => watch(pid, address, cli.output),
 
         Commands::Service {
-            address,
             gateway,
+            gateway6,
+            vpn_interfaces,
             pooling_rate,
+            verify_window,
+            probe_timeout,
+            lease,
+            pinned,
+            drop_routes,
+            connection_source,
+            route_backend,
+            table,
+            rule_priority,
+            socket_owner,
+            socket_group,
+            socket_mode,
+            admin_group,
         } => {
+            let admin_group = admin_group
+                .map(|admin_group| lookup_group(&admin_group).expect("Fail to find admin group"));
             let pooling_rate = Duration::from_millis(pooling_rate as u64);
+            let verify_window =
+                verify_window.map(|verify_window| Duration::from_millis(verify_window as u64));
+            let probe_timeout =
+                probe_timeout.map(|probe_timeout| Duration::from_millis(probe_timeout as u64));
+            let lease = lease.map(|lease| Duration::from_secs(lease as u64 * 60));
+            let vpn_interfaces = VpnInterfaces::new(vpn_interfaces);
+            let gateways = Gateways::resolve(&gateway, gateway6.as_ref(), &vpn_interfaces)
+                .expect("Fail to find gateways");
+            let gateways = RwLock::new(gateways);
+
+            let connection_source = create_connection_source(connection_source)
+                .expect("Fail to create connection source");
+
+            // Place the routes where they take precedence over the VPN routes.
+            let route_backend_kind = route_backend;
+            let vpn_layout = VpnLayout::detect(route_backend_kind).unwrap_or_else(|e| {
+                log::warn!("Fail to detect VPN routing layout: {e}");
 
-            service(&address, &gateway, pooling_rate);
+                VpnLayout::MainTable
+            });
+            let requested_placement = RoutePlacement {
+                table,
+                rule_priority,
+            };
+            let placement = RoutePlacement::select(&vpn_layout, table, rule_priority);
+            log::info!("Detected {vpn_layout}, installing escape routes in {placement}.");
+
+            let route_backend =
+                create_route_backend(route_backend_kind, placement.table.unwrap_or(MAIN_TABLE))
+                    .expect("Fail to create route backend");
+
+            service(
+                SocketSettings {
+                    owner: socket_owner,
+                    group: socket_group,
+                    mode: socket_mode,
+                },
+                ServiceConfig {
+                    gateway,
+                    gateway6,
+                    gateways,
+                    vpn_interfaces,
+                    vpn_down: AtomicBool::new(false),
+                    verify_window,
+                    probe_timeout,
+                    lease,
+                    pinned,
+                    drop_routes,
+                    pooling_rate,
+                    connection_source,
+                    route_target: RwLock::new(RouteTarget {
+                        backend: route_backend,
+                        placement,
+                    }),
+                    route_backend_kind,
+                    requested_placement,
+                    admin_group,
+                },
+            );
         }
     }
 }
 
-fn get_service_address_file() -> PathBuf {
-    let temp_dir = std::env::temp_dir();
+fn get_service_socket_file() -> PathBuf {
     let exe_name = env!("CARGO_PKG_NAME");
 
-    temp_dir.join(format!("{exe_name}.port"))
+    get_state_dir().join(format!("{exe_name}.sock"))
+}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8).map_err(|e| format!("invalid octal mode: {e}"))
 }
@@ -1,22 +1,138 @@
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{eyre, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::{fmt::Display, io::Read, net::IpAddr};
+
+/// Version of the protocol, increased on every incompatible change of the messages.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Largest frame accepted from the other side, in bytes.
+pub const MAX_FRAME_SIZE: u32 = 1024 * 1024;
+
+/// Largest frame accepted by the service, requests and `Hello` are much smaller.
+pub const MAX_REQUEST_SIZE: u32 = 4 * 1024;
+
+/// The service can track the descendants of a process.
+pub const CAPABILITY_CHILDREN: u32 = 1 << 0;
+
+/// The service answers `StatusRequest`.
+pub const CAPABILITY_STATUS: u32 = 1 << 1;
+
+/// The service streams events after `WatchRequest`.
+pub const CAPABILITY_WATCH: u32 = 1 << 2;
+
+/// Capabilities of this build.
+pub const CAPABILITIES: u32 = CAPABILITY_CHILDREN | CAPABILITY_STATUS | CAPABILITY_WATCH;
+
+/// First frame sent by both sides, kept out of `Message` so that its layout never depends on the
+/// protocol version.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Hello {
+    pub version: u32,
+    pub capabilities: u32,
+}
+
+impl Hello {
+    /// Hello of this build.
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES,
+        }
+    }
+
+    pub fn has_capability(&self, capability: u32) -> bool {
+        self.capabilities & capability != 0
+    }
+}
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub enum Message {
-    AttachRequest { pid: u32, delay: u32 },
-    AttachResponse { error: AttachError },
+    AttachRequest {
+        pid: u32,
+        delay: u32,
+        children: bool,
+    },
+    AttachResponse {
+        error: AttachError,
+    },
 
-    DetachRequest { pid: u32 },
-    DetachResponse { error: DetachError },
+    DetachRequest {
+        pid: u32,
+    },
+    DetachResponse {
+        error: DetachError,
+    },
 
     PurgeRequest,
-    PurgeResponse,
+    PurgeResponse {
+        error: PurgeError,
+        addresses: Vec<IpAddr>,
+    },
+    StatusRequest,
+    StatusResponse {
+        processes: Vec<ProcessStatus>,
+        destinations: Vec<DestinationStatus>,
+    },
+    /// Keeps the connection open to receive the events matching the filters.
+    WatchRequest {
+        pid: Option<u32>,
+        address: Option<IpAddr>,
+    },
+    Event {
+        event: Event,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct ProcessStatus {
+    pub pid: u32,
+    pub command_line: String,
+    /// Milliseconds.
+    pub delay: u32,
+    /// Seconds since the Unix epoch.
+    pub attach_time: u64,
+    pub children: bool,
+    /// Descendant PIDs currently tracked, sorted.
+    pub tracked_children: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct DestinationStatus {
+    pub address: IpAddr,
+    pub state: DestinationState,
+    /// Milliseconds spent pending, or waiting for verification in the routing table.
+    pub state_duration: Option<u64>,
+    /// Attached process whose connection triggered the address.
+    pub pid: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DestinationState {
+    Pending,
+    InRoutingTable,
+    Verified,
+    Failed,
+    Suspended,
+}
+
+impl Display for DestinationState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DestinationState::Pending => write!(f, "pending"),
+            DestinationState::InRoutingTable => write!(f, "in routing table"),
+            DestinationState::Verified => write!(f, "verified"),
+            DestinationState::Failed => write!(f, "failed"),
+            DestinationState::Suspended => write!(f, "suspended"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum AttachError {
     Ok,
     ProcessNotFound,
+    PermissionDenied,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
@@ -24,20 +140,214 @@ pub enum DetachError {
     Ok,
     ProcessNotFound,
     UnknownError,
+    PermissionDenied,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PurgeError {
+    Ok,
+    UnknownError,
+    PermissionDenied,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// A tracked process is connecting to the address.
+    ConnectionSeen {
+        pid: u32,
+        address: IpAddr,
+        port: u16,
+    },
+    Pending {
+        pid: Option<u32>,
+        address: IpAddr,
+    },
+    RouteAdded {
+        pid: Option<u32>,
+        address: IpAddr,
+        gateway: IpAddr,
+    },
+    RouteFailed {
+        pid: Option<u32>,
+        address: IpAddr,
+        reason: String,
+    },
+    ProcessExited {
+        pid: u32,
+    },
+    Detached {
+        pid: u32,
+    },
+    Purged,
+}
+
+impl Event {
+    /// Whether the event concerns the process and the address, when they are given.
+    pub fn matches(&self, pid: Option<u32>, address: Option<IpAddr>) -> bool {
+        let (event_pid, event_address) = match self {
+            Event::ConnectionSeen { pid, address, .. } => (Some(*pid), Some(*address)),
+            Event::Pending { pid, address }
+            | Event::RouteAdded { pid, address, .. }
+            | Event::RouteFailed { pid, address, .. } => (*pid, Some(*address)),
+            Event::ProcessExited { pid } | Event::Detached { pid } => (Some(*pid), None),
+
+            // Concerns every process and address.
+            Event::Purged => return true,
+        };
+
+        pid.is_none_or(|pid| event_pid == Some(pid))
+            && address.is_none_or(|address| event_address == Some(address))
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::ConnectionSeen { pid, address, port } => {
+                write!(f, "PID {pid} connecting to {address} port {port}")
+            }
+            Event::Pending { address, .. } => write!(f, "{address} pending"),
+            Event::RouteAdded {
+                address, gateway, ..
+            } => write!(f, "{address} routed through {gateway}"),
+            Event::RouteFailed {
+                address, reason, ..
+            } => write!(f, "{address} failed: {reason}"),
+            Event::ProcessExited { pid } => write!(f, "PID {pid} exited"),
+            Event::Detached { pid } => write!(f, "PID {pid} detached"),
+            Event::Purged => write!(f, "connections purged"),
+        }
+    }
 }
 
-pub fn serialize_to<T, W>(value: &T, writer: W) -> Result<()>
+/// Writes the value as a frame: its length as a little-endian `u32` followed by its bincode
+/// encoding.
+pub fn serialize_to<T, W>(value: &T, mut writer: W) -> Result<()>
 where
     T: serde::Serialize,
     W: std::io::Write,
 {
-    bincode::serialize_into(writer, value).wrap_err("Fail to serialize message.")
+    let payload = bincode::serialize(value).wrap_err("Fail to serialize message.")?;
+    let length = u32::try_from(payload.len())
+        .ok()
+        .filter(|length| *length <= MAX_FRAME_SIZE)
+        .ok_or_else(|| eyre!("Message too large: {} bytes.", payload.len()))?;
+
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(&payload)?;
+
+    Ok(())
 }
 
+/// Reads a frame written by `serialize_to`, refusing the ones larger than `MAX_FRAME_SIZE`.
 pub fn deserialize_from<T, R>(reader: R) -> Result<T>
 where
     T: serde::de::DeserializeOwned,
-    R: std::io::Read,
+    R: Read,
+{
+    deserialize_limited_from(reader, MAX_FRAME_SIZE)
+}
+
+/// Reads a frame sent by a client, refusing the ones larger than `MAX_REQUEST_SIZE`.
+pub fn deserialize_request_from<T, R>(reader: R) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+    R: Read,
 {
-    bincode::deserialize_from::<_, T>(reader).wrap_err("Fail to deserialize message.")
+    deserialize_limited_from(reader, MAX_REQUEST_SIZE)
+}
+
+fn deserialize_limited_from<T, R>(mut reader: R, max_size: u32) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+    R: Read,
+{
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_le_bytes(length);
+    if length > max_size {
+        return Err(eyre!("Message too large: {length} bytes."));
+    }
+
+    // Grow the buffer as bytes arrive, instead of trusting the announced length.
+    let mut payload = Vec::new();
+    reader.take(length as u64).read_to_end(&mut payload)?;
+    if payload.len() != length as usize {
+        return Err(eyre!(
+            "Message truncated: {} of {length} bytes.",
+            payload.len()
+        ));
+    }
+
+    bincode::deserialize(&payload).wrap_err("Fail to deserialize message.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_messages() {
+        let message = Message::AttachRequest {
+            pid: 1234,
+            delay: 500,
+            children: true,
+        };
+        let mut frame = Vec::new();
+        serialize_to(&message, &mut frame).unwrap();
+
+        let payload_length = u32::from_le_bytes(frame[..4].try_into().unwrap());
+        assert_eq!(payload_length as usize, frame.len() - 4);
+        assert!(deserialize_from::<Message, _>(frame.as_slice()).unwrap() == message);
+    }
+
+    #[test]
+    fn round_trips_hello() {
+        let mut frame = Vec::new();
+        serialize_to(&Hello::current(), &mut frame).unwrap();
+
+        let hello: Hello = deserialize_from(frame.as_slice()).unwrap();
+        assert_eq!(hello.version, PROTOCOL_VERSION);
+        assert!(hello.has_capability(CAPABILITY_WATCH));
+    }
+
+    #[test]
+    fn rejects_frames_too_large() {
+        // Only the length is sent, the payload must not even be read.
+        let frame = (MAX_FRAME_SIZE + 1).to_le_bytes();
+        let error = deserialize_from::<Message, _>(frame.as_slice())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("too large"));
+
+        let message = Message::PurgeResponse {
+            error: PurgeError::Ok,
+            addresses: vec![IpAddr::from([10, 0, 0, 1]); MAX_FRAME_SIZE as usize / 4],
+        };
+        assert!(serialize_to(&message, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn limits_requests() {
+        let message = Message::PurgeResponse {
+            error: PurgeError::Ok,
+            addresses: vec![IpAddr::from([10, 0, 0, 1]); MAX_REQUEST_SIZE as usize / 4],
+        };
+        let mut frame = Vec::new();
+        serialize_to(&message, &mut frame).unwrap();
+
+        assert!(deserialize_request_from::<Message, _>(frame.as_slice()).is_err());
+        assert!(deserialize_from::<Message, _>(frame.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let mut frame = Vec::new();
+        serialize_to(&Message::DetachRequest { pid: 1234 }, &mut frame).unwrap();
+        frame.pop();
+
+        assert!(deserialize_from::<Message, _>(frame.as_slice()).is_err());
+        assert!(deserialize_from::<Message, _>(&frame[..2]).is_err());
+    }
 }
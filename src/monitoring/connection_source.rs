@@ -0,0 +1,42 @@
+use super::{
+    ProcfsConnectionSource, SockDiagConnectionSource, TcpConnectionInfo, TcpConnectionStatus,
+};
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+
+/// Source of the TCP sockets visible from the network namespace of a process.
+pub trait ConnectionSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the TCP sockets that are in any of the given states.
+    fn get_connections(
+        &self,
+        pid: u32,
+        states: &[TcpConnectionStatus],
+    ) -> Result<Vec<TcpConnectionInfo>>;
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ConnectionSourceKind {
+    /// Use netlink when available, falling back to procfs.
+    Auto,
+    /// Ask the kernel for sockets through `NETLINK_SOCK_DIAG`.
+    Netlink,
+    /// Parse `/proc/<pid>/net/tcp` and `/proc/<pid>/net/tcp6`.
+    Procfs,
+}
+
+pub fn create_connection_source(kind: ConnectionSourceKind) -> Result<Box<dyn ConnectionSource>> {
+    match kind {
+        ConnectionSourceKind::Auto => match SockDiagConnectionSource::new() {
+            Ok(source) => Ok(Box::new(source)),
+            Err(e) => {
+                log::warn!("Netlink connection source unavailable, falling back to procfs: {e}");
+
+                Ok(Box::new(ProcfsConnectionSource))
+            }
+        },
+        ConnectionSourceKind::Netlink => Ok(Box::new(SockDiagConnectionSource::new()?)),
+        ConnectionSourceKind::Procfs => Ok(Box::new(ProcfsConnectionSource)),
+    }
+}
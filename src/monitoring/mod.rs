@@ -1,5 +1,23 @@
+mod connection_source;
+mod process_command_line;
+mod process_credentials;
+mod process_sockets;
+mod process_start_time;
+mod process_tree;
+mod procfs_connection_source;
+mod reachability_probe;
+mod sock_diag_connection_source;
 mod tcp_connection_info;
 mod tcp_connection_status;
 
+pub use connection_source::{create_connection_source, ConnectionSource, ConnectionSourceKind};
+pub use process_command_line::get_command_line;
+pub use process_credentials::{get_process_groups, get_process_uid};
+pub use process_sockets::get_socket_inodes;
+pub use process_start_time::get_process_start_time;
+pub use process_tree::get_process_tree;
+pub use procfs_connection_source::ProcfsConnectionSource;
+pub use reachability_probe::probe_reachability;
+pub use sock_diag_connection_source::SockDiagConnectionSource;
 pub use tcp_connection_info::TcpConnectionInfo;
 pub use tcp_connection_status::TcpConnectionStatus;
@@ -0,0 +1,13 @@
+use color_eyre::eyre::Result;
+
+/// Returns the arguments of the process joined by spaces, read from `/proc/<pid>/cmdline`.
+pub fn get_command_line(pid: u32) -> Result<String> {
+    let command_line = std::fs::read(format!("/proc/{pid}/cmdline"))?;
+
+    Ok(command_line
+        .split(|byte| *byte == 0)
+        .filter(|argument| !argument.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
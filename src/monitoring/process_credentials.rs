@@ -0,0 +1,28 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Returns the real user ID of the process, read from `/proc/<pid>/status`.
+pub fn get_process_uid(pid: u32) -> Result<u32> {
+    let ids = get_status_field(pid, "Uid:")?;
+
+    ids.first()
+        .copied()
+        .ok_or_else(|| eyre!("no user ID for process {pid}"))
+}
+
+/// Returns the supplementary groups of the process, read from `/proc/<pid>/status`.
+pub fn get_process_groups(pid: u32) -> Result<Vec<u32>> {
+    get_status_field(pid, "Groups:")
+}
+
+fn get_status_field(pid: u32, name: &str) -> Result<Vec<u32>> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status"))?;
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix(name))
+        .ok_or_else(|| eyre!("no {name} field for process {pid}"))?;
+
+    Ok(line
+        .split_whitespace()
+        .filter_map(|id| id.parse().ok())
+        .collect())
+}
@@ -0,0 +1,30 @@
+use color_eyre::eyre::Result;
+use std::collections::HashSet;
+
+/// Collects the inodes of every socket opened by the process.
+pub fn get_socket_inodes(pid: u32) -> Result<HashSet<u64>> {
+    let mut inodes = HashSet::new();
+
+    for entry in std::fs::read_dir(format!("/proc/{pid}/fd"))? {
+        // File descriptors can be closed while we are iterating them.
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Ok(target) = std::fs::read_link(entry.path()) else {
+            continue;
+        };
+
+        let Some(inode) = target
+            .to_str()
+            .and_then(|target| target.strip_prefix("socket:["))
+            .and_then(|target| target.strip_suffix(']'))
+            .and_then(|inode| inode.parse().ok())
+        else {
+            continue;
+        };
+
+        inodes.insert(inode);
+    }
+
+    Ok(inodes)
+}
@@ -0,0 +1,21 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Returns the time the process started after boot, in clock ticks, read from `/proc/<pid>/stat`.
+///
+/// Together with the PID, it identifies the process even after the PID is reused.
+pub fn get_process_start_time(pid: u32) -> Result<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+
+    // The command name can contain spaces and parentheses, the fields start after the last one.
+    let fields = stat
+        .rsplit_once(')')
+        .map(|(_, fields)| fields)
+        .ok_or_else(|| eyre!("malformed stat for process {pid}"))?;
+
+    // Start time is the 22nd field, the 20th after the command name.
+    fields
+        .split_whitespace()
+        .nth(19)
+        .and_then(|start_time| start_time.parse().ok())
+        .ok_or_else(|| eyre!("no start time for process {pid}"))
+}
@@ -0,0 +1,41 @@
+use color_eyre::eyre::Result;
+
+/// Returns the process followed by all of its descendants, discovered through
+/// `/proc/<pid>/task/*/children`.
+pub fn get_process_tree(pid: u32) -> Result<Vec<u32>> {
+    let mut processes = vec![pid];
+
+    // The root process must exist, descendants can exit at any time.
+    let mut children = get_children(pid)?;
+    while let Some(child) = children.pop() {
+        if processes.contains(&child) {
+            continue;
+        }
+        processes.push(child);
+
+        children.extend(get_children(child).unwrap_or_default());
+    }
+
+    Ok(processes)
+}
+
+fn get_children(pid: u32) -> Result<Vec<u32>> {
+    let mut children = Vec::new();
+
+    for task in std::fs::read_dir(format!("/proc/{pid}/task"))? {
+        let Ok(task) = task else {
+            continue;
+        };
+        let Ok(task_children) = std::fs::read_to_string(task.path().join("children")) else {
+            continue;
+        };
+
+        children.extend(
+            task_children
+                .split_whitespace()
+                .filter_map(|child| child.parse::<u32>().ok()),
+        );
+    }
+
+    Ok(children)
+}
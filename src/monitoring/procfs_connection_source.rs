@@ -0,0 +1,43 @@
+use super::{ConnectionSource, TcpConnectionInfo, TcpConnectionStatus};
+use color_eyre::eyre::Result;
+
+/// Parses the TCP tables exposed in `/proc/<pid>/net`.
+pub struct ProcfsConnectionSource;
+
+impl ConnectionSource for ProcfsConnectionSource {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn get_connections(
+        &self,
+        pid: u32,
+        states: &[TcpConnectionStatus],
+    ) -> Result<Vec<TcpConnectionInfo>> {
+        let tcp_file = std::fs::read_to_string(format!("/proc/{}/net/tcp", pid))?;
+
+        // The IPv6 table is missing when IPv6 is disabled in the kernel.
+        let tcp6_file = match std::fs::read_to_string(format!("/proc/{}/net/tcp6", pid)) {
+            Ok(tcp6_file) => tcp6_file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let connections = tcp_file
+            .lines()
+            .skip(1)
+            .chain(tcp6_file.lines().skip(1))
+            .filter_map(|line| match TcpConnectionInfo::try_from(line) {
+                Ok(connection) => Some(connection),
+                Err(_) => {
+                    log::debug!("Skipping malformed TCP table line: {line}");
+
+                    None
+                }
+            })
+            .filter(|connection| states.contains(connection.status()))
+            .collect();
+
+        Ok(connections)
+    }
+}
@@ -0,0 +1,17 @@
+use socket2::{Domain, Socket, Type};
+use std::{io, net::SocketAddr, time::Duration};
+
+/// Tries to connect to the address through the given interface, bypassing the routing table
+/// entries of other interfaces.
+pub fn probe_reachability(
+    address: SocketAddr,
+    device: Option<&str>,
+    timeout: Duration,
+) -> io::Result<()> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if let Some(device) = device {
+        socket.bind_device(Some(device.as_bytes()))?;
+    }
+
+    socket.connect_timeout(&address.into(), timeout)
+}
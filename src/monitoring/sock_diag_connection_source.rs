@@ -0,0 +1,170 @@
+use super::{ConnectionSource, TcpConnectionInfo, TcpConnectionStatus};
+use crate::netlink::{NetlinkSocket, NLM_F_DUMP};
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+};
+
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+
+const INET_DIAG_REQ_LEN: usize = 56;
+const INET_DIAG_MSG_LEN: usize = 72;
+
+/// Asks the kernel for sockets through `NETLINK_SOCK_DIAG`, filtering them by state in the kernel.
+///
+/// Netlink only sees the sockets of the network namespace the service runs in.
+pub struct SockDiagConnectionSource {
+    socket: Mutex<NetlinkSocket>,
+}
+
+impl SockDiagConnectionSource {
+    pub fn new() -> Result<Self> {
+        let socket = NetlinkSocket::open(libc::NETLINK_SOCK_DIAG)?;
+
+        Ok(Self {
+            socket: Mutex::new(socket),
+        })
+    }
+
+    fn dump(
+        socket: &mut NetlinkSocket,
+        family: u8,
+        states: &[TcpConnectionStatus],
+    ) -> Result<Vec<TcpConnectionInfo>> {
+        let states = states
+            .iter()
+            .fold(0u32, |mask, status| mask | 1 << (*status as u8));
+
+        // struct inet_diag_req_v2 with an empty socket id.
+        let mut request = [0u8; INET_DIAG_REQ_LEN];
+        request[0] = family;
+        request[1] = libc::IPPROTO_TCP as u8;
+        request[4..8].copy_from_slice(&states.to_ne_bytes());
+
+        let messages = socket.request(SOCK_DIAG_BY_FAMILY, NLM_F_DUMP, &request)?;
+
+        let connections = messages
+            .iter()
+            .filter(|message| message.message_type == SOCK_DIAG_BY_FAMILY)
+            .filter_map(|message| parse_inet_diag_msg(&message.payload))
+            .collect();
+
+        Ok(connections)
+    }
+}
+
+impl ConnectionSource for SockDiagConnectionSource {
+    fn name(&self) -> &'static str {
+        "netlink"
+    }
+
+    fn get_connections(
+        &self,
+        _pid: u32,
+        states: &[TcpConnectionStatus],
+    ) -> Result<Vec<TcpConnectionInfo>> {
+        let Ok(mut socket) = self.socket.lock() else {
+            return Err(eyre!("Fail to lock netlink socket."));
+        };
+
+        let mut connections = Self::dump(&mut socket, libc::AF_INET as u8, states)?;
+        connections.extend(Self::dump(&mut socket, libc::AF_INET6 as u8, states)?);
+
+        Ok(connections)
+    }
+}
+
+/// Parses a `struct inet_diag_msg`.
+fn parse_inet_diag_msg(payload: &[u8]) -> Option<TcpConnectionInfo> {
+    if payload.len() < INET_DIAG_MSG_LEN {
+        return None;
+    }
+
+    let family = payload[0] as i32;
+    let status = payload[1].try_into().ok()?;
+
+    // The destination address follows the ports and the source address.
+    let destination = &payload[24..40];
+    let remote_address = match family {
+        libc::AF_INET => IpAddr::V4(Ipv4Addr::new(
+            destination[0],
+            destination[1],
+            destination[2],
+            destination[3],
+        )),
+        libc::AF_INET6 => {
+            let address = Ipv6Addr::from(<[u8; 16]>::try_from(destination).ok()?);
+
+            match address.to_ipv4_mapped() {
+                Some(address) => IpAddr::V4(address),
+                None => IpAddr::V6(address),
+            }
+        }
+
+        _ => return None,
+    };
+
+    let remote_port = u16::from_be_bytes([payload[6], payload[7]]);
+    let inode = u32::from_ne_bytes(payload[68..72].try_into().ok()?) as u64;
+
+    Some(TcpConnectionInfo::new(
+        remote_address,
+        remote_port,
+        status,
+        inode,
+    ))
+}
+
+// The inode is in host byte order, these messages come from a little-endian machine.
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+
+    /// Socket connected from 127.0.0.1:44356 to 127.0.0.1:7002.
+    const IPV4_MSG: &str = "02010000ad441b5a7f0000010000000000000000000000007f000001000000000000000000000000000000002d000000000000000000000000000000000000000000000000aa0000";
+
+    /// Socket connected from ::ffff:127.0.0.1:44344 to ::ffff:127.0.0.1:7002.
+    const IPV4_MAPPED_MSG: &str = "0a010000ad381b5a00000000000000000000ffff7f00000100000000000000000000ffff7f00000100000000310000000000000000000000000000000000000000000000ffa90000";
+
+    fn decode(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parses_ipv4_message() {
+        let info = parse_inet_diag_msg(&decode(IPV4_MSG)).unwrap();
+
+        assert_eq!(
+            info,
+            TcpConnectionInfo::new(
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                7002,
+                TcpConnectionStatus::Established,
+                43520
+            )
+        );
+        assert_eq!(info.inode(), 43520);
+    }
+
+    #[test]
+    fn folds_ipv4_mapped_address() {
+        let info = parse_inet_diag_msg(&decode(IPV4_MAPPED_MSG)).unwrap();
+
+        assert_eq!(info.remote_address(), &IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(info.remote_port(), 7002);
+        assert_eq!(info.inode(), 43519);
+    }
+
+    #[test]
+    fn rejects_short_or_unknown_messages() {
+        let mut payload = decode(IPV4_MSG);
+        assert!(parse_inet_diag_msg(&payload[..INET_DIAG_MSG_LEN - 1]).is_none());
+
+        payload[0] = libc::AF_UNIX as u8;
+        assert!(parse_inet_diag_msg(&payload).is_none());
+    }
+}
@@ -1,20 +1,44 @@
 use super::TcpConnectionStatus;
-use std::net::{AddrParseError, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 #[derive(Debug)]
 pub struct TcpConnectionInfo {
-    remote_address: Ipv4Addr,
+    remote_address: IpAddr,
+    remote_port: u16,
     status: TcpConnectionStatus,
+    inode: u64,
 }
 
 impl TcpConnectionInfo {
-    pub fn remote_address(&self) -> &Ipv4Addr {
+    pub fn new(
+        remote_address: IpAddr,
+        remote_port: u16,
+        status: TcpConnectionStatus,
+        inode: u64,
+    ) -> Self {
+        Self {
+            remote_address,
+            remote_port,
+            status,
+            inode,
+        }
+    }
+
+    pub fn remote_address(&self) -> &IpAddr {
         &self.remote_address
     }
 
+    pub fn remote_port(&self) -> u16 {
+        self.remote_port
+    }
+
     pub fn status(&self) -> &TcpConnectionStatus {
         &self.status
     }
+
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
 }
 
 impl TryFrom<&str> for TcpConnectionInfo {
@@ -24,7 +48,7 @@ impl TryFrom<&str> for TcpConnectionInfo {
         let mut columns = value.split_whitespace().skip(2); // Skip index and local address.
 
         let column = columns.next().ok_or(ParseConnectionInfoError)?;
-        let remote_address = parse_ipv4(column).map_err(|_| ParseConnectionInfoError)?;
+        let (remote_address, remote_port) = parse_address(column)?;
 
         let column = columns.next().ok_or(ParseConnectionInfoError)?;
         let status = u8::from_str_radix(column, 16)
@@ -32,30 +56,128 @@ impl TryFrom<&str> for TcpConnectionInfo {
             .try_into()
             .map_err(|_| ParseConnectionInfoError)?;
 
+        // Skip queues, timer, retransmits, uid and timeout.
+        let column = columns.nth(5).ok_or(ParseConnectionInfoError)?;
+        let inode = column.parse().map_err(|_| ParseConnectionInfoError)?;
+
         Ok(TcpConnectionInfo {
             remote_address,
+            remote_port,
             status,
+            inode,
         })
     }
 }
 
 impl PartialEq for TcpConnectionInfo {
     fn eq(&self, other: &TcpConnectionInfo) -> bool {
-        self.remote_address == other.remote_address && self.status == other.status
+        self.remote_address == other.remote_address
+            && self.remote_port == other.remote_port
+            && self.status == other.status
     }
 }
 
-fn parse_ipv4(text: &str) -> Result<Ipv4Addr, AddrParseError> {
-    let text = format!(
-        "{}.{}.{}.{}",
-        u8::from_str_radix(&text[6..8], 16).unwrap(),
-        u8::from_str_radix(&text[4..6], 16).unwrap(),
-        u8::from_str_radix(&text[2..4], 16).unwrap(),
-        u8::from_str_radix(&text[0..2], 16).unwrap(),
-    );
+/// Parses an `address:port` column from `/proc/<pid>/net/tcp` or `/proc/<pid>/net/tcp6`.
+///
+/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are folded into plain IPv4 addresses.
+fn parse_address(column: &str) -> Result<(IpAddr, u16), ParseConnectionInfoError> {
+    let (address, port) = column.split_once(':').ok_or(ParseConnectionInfoError)?;
+    let port = u16::from_str_radix(port, 16).map_err(|_| ParseConnectionInfoError)?;
+
+    let address = match address.len() {
+        8 => IpAddr::V4(parse_ipv4(address)?),
+        32 => {
+            let address = parse_ipv6(address)?;
+
+            match address.to_ipv4_mapped() {
+                Some(address) => IpAddr::V4(address),
+                None => IpAddr::V6(address),
+            }
+        }
+
+        _ => return Err(ParseConnectionInfoError),
+    };
+
+    Ok((address, port))
+}
+
+/// The kernel prints each 32-bit word in host byte order, so the bytes of every word are reversed.
+fn parse_word(text: &str) -> Result<[u8; 4], ParseConnectionInfoError> {
+    let word = u32::from_str_radix(text, 16).map_err(|_| ParseConnectionInfoError)?;
+
+    Ok(word.to_ne_bytes())
+}
+
+fn parse_ipv4(text: &str) -> Result<Ipv4Addr, ParseConnectionInfoError> {
+    Ok(Ipv4Addr::from(parse_word(text)?))
+}
+
+fn parse_ipv6(text: &str) -> Result<Ipv6Addr, ParseConnectionInfoError> {
+    let mut octets = [0u8; 16];
+    for (index, chunk) in octets.chunks_exact_mut(4).enumerate() {
+        let word = text
+            .get(index * 8..(index + 1) * 8)
+            .ok_or(ParseConnectionInfoError)?;
+
+        chunk.copy_from_slice(&parse_word(word)?);
+    }
 
-    text.parse::<Ipv4Addr>()
+    Ok(Ipv6Addr::from(octets))
 }
 
 #[derive(Debug)]
 pub struct ParseConnectionInfoError;
+
+// The kernel output depends on the byte order, these lines come from a little-endian machine.
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ipv4_line() {
+        let line = "   2: 0100007F:AB14 0909090A:1E61 02 00000000:00000000 01:00000064 00000002     0        0 43210 1 0000000000000000 100 0 0 10 -1";
+
+        let info = TcpConnectionInfo::try_from(line).unwrap();
+
+        assert_eq!(
+            info,
+            TcpConnectionInfo::new(
+                IpAddr::V4(Ipv4Addr::new(10, 9, 9, 9)),
+                7777,
+                TcpConnectionStatus::SynSent,
+                43210
+            )
+        );
+        assert_eq!(info.inode(), 43210);
+    }
+
+    #[test]
+    fn folds_ipv4_mapped_address() {
+        let line = "   3: 0000000000000000FFFF00000100007F:AB14 0000000000000000FFFF00000100007F:1B59 01 00000000:00000000 00:00000000 00000000     0        0 43198 2 000000007fdf3b55 20 0 0 10 -1";
+
+        let info = TcpConnectionInfo::try_from(line).unwrap();
+
+        assert_eq!(info.remote_address(), &IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(info.remote_port(), 7001);
+        assert_eq!(info.status(), &TcpConnectionStatus::Established);
+        assert_eq!(info.inode(), 43198);
+    }
+
+    #[test]
+    fn parses_ipv6_line() {
+        let line = "   1: 00000000000000000000000001000000:C49C 00000000000000000000000001000000:1B59 01 00000000:00000000 00:00000000 00000000     0        0 43199 2 00000000065f6ca7 20 0 0 10 -1";
+
+        let info = TcpConnectionInfo::try_from(line).unwrap();
+
+        assert_eq!(info.remote_address(), &IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(info.remote_port(), 7001);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(TcpConnectionInfo::try_from("").is_err());
+        assert!(TcpConnectionInfo::try_from("   0: 0100007F:AB14 0909090A 02").is_err());
+        assert!(TcpConnectionInfo::try_from("   0: 0100007F:AB14 09090A:1E61 02").is_err());
+        assert!(TcpConnectionInfo::try_from("   0: 0100007F:AB14 0909090A:1E61 0F").is_err());
+    }
+}
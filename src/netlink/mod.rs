@@ -0,0 +1,186 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+pub const NLM_F_REQUEST: u16 = 0x01;
+pub const NLM_F_MULTI: u16 = 0x02;
+pub const NLM_F_ACK: u16 = 0x04;
+pub const NLM_F_DUMP: u16 = 0x300;
+
+const NLMSG_ERROR: u16 = 0x02;
+const NLMSG_DONE: u16 = 0x03;
+
+const HEADER_LEN: usize = 16;
+const RECEIVE_BUFFER_LEN: usize = 64 * 1024;
+
+/// Message received from the kernel.
+pub struct NetlinkMessage {
+    pub message_type: u16,
+    pub payload: Vec<u8>,
+}
+
+/// Blocking netlink socket speaking to the kernel.
+pub struct NetlinkSocket {
+    fd: OwnedFd,
+    sequence: u32,
+}
+
+impl NetlinkSocket {
+    pub fn open(protocol: libc::c_int) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let result = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd, sequence: 0 })
+    }
+
+    /// Sends a request and collects every reply until the kernel is done answering.
+    ///
+    /// Errors reported by the kernel through `NLMSG_ERROR` are returned as OS errors, so callers
+    /// can inspect them with [`io::Error::raw_os_error`].
+    pub fn request(
+        &mut self,
+        message_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> io::Result<Vec<NetlinkMessage>> {
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+        message.extend_from_slice(&((HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&(flags | NLM_F_REQUEST).to_ne_bytes());
+        message.extend_from_slice(&self.sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+
+        let sent = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut messages = Vec::new();
+        let mut buffer = vec![0u8; RECEIVE_BUFFER_LEN];
+        loop {
+            let received = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if received < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut data = &buffer[..received as usize];
+            while data.len() >= HEADER_LEN {
+                let length = u32::from_ne_bytes(data[0..4].try_into().unwrap()) as usize;
+                let reply_type = u16::from_ne_bytes(data[4..6].try_into().unwrap());
+                let reply_flags = u16::from_ne_bytes(data[6..8].try_into().unwrap());
+                let reply_sequence = u32::from_ne_bytes(data[8..12].try_into().unwrap());
+                if length < HEADER_LEN || length > data.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Truncated netlink message.",
+                    ));
+                }
+
+                let reply_payload = &data[HEADER_LEN..length];
+                data = &data[align(length).min(data.len())..];
+
+                // Ignore stale replies to earlier requests.
+                if reply_sequence != self.sequence {
+                    continue;
+                }
+
+                match reply_type {
+                    NLMSG_DONE => return Ok(messages),
+                    NLMSG_ERROR => {
+                        let code = reply_payload
+                            .get(0..4)
+                            .map(|code| i32::from_ne_bytes(code.try_into().unwrap()))
+                            .unwrap_or(-libc::EIO);
+
+                        return match code {
+                            0 => Ok(messages),
+                            code => Err(io::Error::from_raw_os_error(-code)),
+                        };
+                    }
+
+                    _ => messages.push(NetlinkMessage {
+                        message_type: reply_type,
+                        payload: reply_payload.to_vec(),
+                    }),
+                }
+
+                if reply_flags & NLM_F_MULTI == 0 && flags & NLM_F_ACK == 0 {
+                    return Ok(messages);
+                }
+            }
+        }
+    }
+}
+
+/// Rounds a length up to the netlink alignment.
+pub fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+/// Appends a `struct rtattr` to the message.
+pub fn push_attribute(message: &mut Vec<u8>, attribute_type: u16, data: &[u8]) {
+    let length = 4 + data.len();
+
+    message.extend_from_slice(&(length as u16).to_ne_bytes());
+    message.extend_from_slice(&attribute_type.to_ne_bytes());
+    message.extend_from_slice(data);
+    message.resize(message.len() + align(length) - length, 0);
+}
+
+/// Splits a list of `struct rtattr` into their types and data.
+pub fn parse_attributes(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+
+    while data.len() >= 4 {
+        let length = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        let attribute_type = u16::from_ne_bytes([data[2], data[3]]);
+        if length < 4 || length > data.len() {
+            break;
+        }
+
+        attributes.push((attribute_type, &data[4..length]));
+        data = &data[align(length).min(data.len())..];
+    }
+
+    attributes
+}
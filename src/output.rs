@@ -0,0 +1,175 @@
+use crate::messages::{DestinationStatus, ProcessStatus};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{fmt::Display, net::IpAddr};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Sentences meant to be read by people.
+    Text,
+    /// One JSON object per result or event.
+    Json,
+}
+
+/// Reasons for a client command to fail, each with its own exit code.
+#[derive(Debug)]
+pub enum ClientError {
+    ServiceUnavailable(String),
+    UntrustedService(String),
+    IncompatibleVersion(u32),
+    Unsupported(&'static str),
+    ProcessNotFound(u32),
+    PermissionDenied(u32),
+    /// Only root and the admin group can do it.
+    AdminOnly(&'static str),
+    ServiceError,
+    Protocol(String),
+    LaunchFailed(String),
+}
+
+impl ClientError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientError::ServiceUnavailable(_) => "service_unavailable",
+            ClientError::UntrustedService(_) => "untrusted_service",
+            ClientError::IncompatibleVersion(_) => "incompatible_version",
+            ClientError::Unsupported(_) => "unsupported",
+            ClientError::ProcessNotFound(_) => "process_not_found",
+            ClientError::PermissionDenied(_) => "permission_denied",
+            ClientError::AdminOnly(_) => "permission_denied",
+            ClientError::ServiceError => "service_error",
+            ClientError::Protocol(_) => "protocol_error",
+            ClientError::LaunchFailed(_) => "launch_failed",
+        }
+    }
+
+    /// Exit code of the client, starting at 3 since clap exits with 2 on invalid arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ClientError::ProcessNotFound(_) => 3,
+            ClientError::PermissionDenied(_) => 4,
+            ClientError::AdminOnly(_) => 4,
+            ClientError::ServiceUnavailable(_) => 5,
+            ClientError::UntrustedService(_) => 6,
+            ClientError::IncompatibleVersion(_) => 7,
+            ClientError::Unsupported(_) => 8,
+            ClientError::ServiceError => 9,
+            ClientError::Protocol(_) => 10,
+            ClientError::LaunchFailed(_) => 11,
+        }
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::ServiceUnavailable(e) => write!(f, "Service unavailable: {e}"),
+            ClientError::UntrustedService(e) => write!(f, "Untrusted service: {e}"),
+            ClientError::IncompatibleVersion(version) => write!(
+                f,
+                "Incompatible service version: {version} (expected {}).",
+                crate::messages::PROTOCOL_VERSION
+            ),
+            ClientError::Unsupported(feature) => write!(f, "Service can't {feature}."),
+            ClientError::ProcessNotFound(pid) => write!(f, "Process not found: {pid}"),
+            ClientError::PermissionDenied(pid) => write!(
+                f,
+                "Permission denied: process {pid} belongs to another user."
+            ),
+            ClientError::AdminOnly(action) => write!(
+                f,
+                "Permission denied: only root and the admin group can {action}."
+            ),
+            ClientError::ServiceError => write!(f, "Unknown error occured in service!"),
+            ClientError::Protocol(e) => write!(f, "Fail to talk to service: {e}"),
+            ClientError::LaunchFailed(e) => write!(f, "Fail to launch process: {e}"),
+        }
+    }
+}
+
+impl From<color_eyre::Report> for ClientError {
+    fn from(e: color_eyre::Report) -> Self {
+        ClientError::Protocol(e.to_string())
+    }
+}
+
+/// Result of a client command.
+#[derive(Serialize)]
+pub struct Report {
+    command: &'static str,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    /// Printed as is in text output.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    message: String,
+    pub pids: Vec<u32>,
+    pub addresses: Vec<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processes: Option<Vec<ProcessStatus>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destinations: Option<Vec<DestinationStatus>>,
+}
+
+impl Report {
+    pub fn new(command: &'static str) -> Self {
+        Self {
+            command,
+            success: true,
+            error: None,
+            message: String::new(),
+            pids: Vec::new(),
+            addresses: Vec::new(),
+            processes: None,
+            destinations: None,
+        }
+    }
+
+    /// Prints the report, exiting with the code of the error if the command failed.
+    pub fn finish(self, output: OutputFormat, result: Result<String, ClientError>) {
+        if let Some(exit_code) = self.print(output, result) {
+            std::process::exit(exit_code);
+        }
+    }
+
+    /// Prints the report, returning the exit code of the error if the command failed.
+    pub fn print(
+        mut self,
+        output: OutputFormat,
+        result: Result<String, ClientError>,
+    ) -> Option<i32> {
+        let exit_code = match result {
+            Ok(message) => {
+                self.message = message;
+
+                None
+            }
+            Err(e) => {
+                self.success = false;
+                self.error = Some(e.kind());
+                self.message = e.to_string();
+
+                Some(e.exit_code())
+            }
+        };
+
+        match output {
+            OutputFormat::Json => {
+                // Results are already structured, only errors need an explanation.
+                if self.success {
+                    self.message.clear();
+                }
+
+                match serde_json::to_string(&self) {
+                    Ok(json) => println!("{json}"),
+                    Err(e) => eprintln!("Fail to serialize report: {e}"),
+                }
+            }
+            OutputFormat::Text if self.message.is_empty() => { /* Do nothing. */ }
+            OutputFormat::Text if self.success => println!("{}", self.message),
+            OutputFormat::Text => eprintln!("{}", self.message),
+        }
+
+        exit_code
+    }
+}
@@ -2,44 +2,170 @@ use color_eyre::eyre::{eyre, Result};
 use std::{
     cell::Cell,
     collections::HashMap,
-    sync::{mpsc::Sender, Arc, Mutex, OnceLock},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex, OnceLock,
+    },
     thread::JoinHandle,
+    time::SystemTime,
 };
 
-type Processes = Arc<Mutex<HashMap<u32, (Cell<Option<JoinHandle<()>>>, Sender<()>)>>>;
+type Processes = Arc<Mutex<HashMap<u32, Process>>>;
 
 static PROCESSES: OnceLock<Processes> = OnceLock::new();
 
-pub fn add_process(pid: u32, join_handler: JoinHandle<()>, exit_sender: Sender<()>) -> Result<()> {
+/// Identifies each attachment, so a finishing thread never removes the one replacing it.
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+/// What a client asked for when attaching to a process.
+#[derive(Clone)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub command_line: String,
+    pub delay: u32,
+    pub attach_time: SystemTime,
+    pub children: bool,
+    /// Descendant PIDs currently tracked along with the process, sorted.
+    pub tracked_children: Vec<u32>,
+    /// Start time of the process, to tell it apart from a later process reusing its PID.
+    pub start_time: u64,
+}
+
+struct Process {
+    token: u64,
+    join_handler: Cell<Option<JoinHandle<()>>>,
+    exit_sender: Sender<()>,
+    info: ProcessInfo,
+}
+
+/// Registers a process before its monitoring thread starts, so the thread can reap it.
+///
+/// A previous attachment to the same PID is replaced and its monitoring thread stopped. Returns
+/// the token of the new attachment.
+pub fn add_process(info: ProcessInfo, exit_sender: Sender<()>) -> Result<u64> {
+    let pid = info.pid;
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::SeqCst);
+
+    let previous = {
+        let processes = PROCESSES.get_or_init(Default::default);
+        let Ok(mut processes) = processes.lock() else {
+            return Err(eyre!("Fail to lock processes collection."));
+        };
+
+        let process = Process {
+            token,
+            join_handler: Cell::new(None),
+            exit_sender,
+            info,
+        };
+        processes.insert(pid, process)
+    };
+    if let Some(previous) = previous {
+        log::info!("Stopping previous tracking of PID: {pid}");
+        if let Err(e) = stop_process(previous) {
+            log::error!("Fail to stop previous tracking of PID: {pid} with error: {e}");
+        }
+    }
+
+    Ok(token)
+}
+
+/// Keeps the monitoring thread of the process to wait for it when detaching.
+pub fn set_join_handler(pid: u32, token: u64, join_handler: JoinHandle<()>) -> Result<()> {
     let processes = PROCESSES.get_or_init(Default::default);
-    let Ok(mut processes) = processes.lock() else {
+    let Ok(processes) = processes.lock() else {
         return Err(eyre!("Fail to lock processes collection."));
     };
 
-    processes.insert(pid, (Cell::new(Some(join_handler)), exit_sender));
+    // The thread is already done when the attachment was reaped or replaced.
+    if let Some(process) = processes.get(&pid) {
+        if process.token == token {
+            process.join_handler.set(Some(join_handler));
+        }
+    }
 
     Ok(())
 }
 
-pub fn remove_process_and_trigger_exit(pid: u32) -> Result<bool> {
+/// Records the descendant PIDs the monitoring thread of an attachment currently tracks.
+pub fn set_tracked_children(pid: u32, token: u64, tracked_children: Vec<u32>) -> Result<()> {
     let processes = PROCESSES.get_or_init(Default::default);
-
-    let Ok(processes) = processes.lock() else {
+    let Ok(mut processes) = processes.lock() else {
         return Err(eyre!("Fail to lock processes collection."));
     };
 
-    let Some((join_handler, exit_sender)) = processes.get(&pid) else {
+    if let Some(process) = processes.get_mut(&pid) {
+        if process.token == token {
+            process.info.tracked_children = tracked_children;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a detached process once its monitoring thread is done.
+pub fn remove_process_and_trigger_exit(pid: u32) -> Result<bool> {
+    let process = {
+        let processes = PROCESSES.get_or_init(Default::default);
+        let Ok(mut processes) = processes.lock() else {
+            return Err(eyre!("Fail to lock processes collection."));
+        };
+
+        processes.remove(&pid)
+    };
+    let Some(process) = process else {
         return Ok(false);
     };
 
-    // Send signal to stop monitoring process.
-    exit_sender.send(())?;
+    stop_process(process)?;
+
+    Ok(true)
+}
 
-    // Wait for process monitoring thread to finish.
-    let Some(join_handler) = join_handler.take() else {
-        return Err(eyre!("Fail to take thread handler."));
+/// Removes an attachment whose monitoring thread stopped on its own, returning false when it was
+/// detached or replaced meanwhile.
+pub fn reap_process(pid: u32, token: u64) -> Result<bool> {
+    let processes = PROCESSES.get_or_init(Default::default);
+    let Ok(mut processes) = processes.lock() else {
+        return Err(eyre!("Fail to lock processes collection."));
     };
-    join_handler.join().unwrap();
+
+    if processes.get(&pid).map(|process| process.token) != Some(token) {
+        return Ok(false);
+    }
+    processes.remove(&pid);
 
     Ok(true)
 }
+
+/// Returns the processes currently attached, sorted by PID.
+pub fn list_processes() -> Result<Vec<ProcessInfo>> {
+    let processes = PROCESSES.get_or_init(Default::default);
+    let Ok(processes) = processes.lock() else {
+        return Err(eyre!("Fail to lock processes collection."));
+    };
+
+    let mut processes: Vec<_> = processes
+        .values()
+        .map(|process| process.info.clone())
+        .collect();
+    processes.sort_by_key(|info| info.pid);
+
+    Ok(processes)
+}
+
+/// Stops the monitoring thread of a process already removed from the collection.
+fn stop_process(process: Process) -> Result<()> {
+    // Send signal to stop monitoring process, unless it just stopped on its own.
+    process.exit_sender.send(()).unwrap_or_default();
+
+    // Wait for process monitoring thread to finish, without holding the collection locked.
+    if let Some(join_handler) = process.join_handler.take() {
+        join_handler
+            .join()
+            .map_err(|_| eyre!("Process monitoring thread panicked."))?;
+    }
+
+    Ok(())
+}
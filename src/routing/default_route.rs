@@ -0,0 +1,103 @@
+use super::{Gateway, VpnInterfaces};
+use color_eyre::eyre::Result;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+
+/// Finds the gateway of the default route with the lowest metric that doesn't go through a VPN
+/// interface, for the address family of the given address.
+pub fn find_default_gateway(
+    family: IpAddr,
+    vpn_interfaces: &VpnInterfaces,
+) -> Result<Option<Gateway>> {
+    let routes = match family {
+        IpAddr::V4(_) => read_ipv4_default_routes()?,
+        IpAddr::V6(_) => read_ipv6_default_routes()?,
+    };
+
+    let gateway = routes
+        .into_iter()
+        .filter(|(gateway, _)| {
+            !gateway
+                .device
+                .as_deref()
+                .is_some_and(|device| vpn_interfaces.contains(device))
+        })
+        .min_by_key(|(_, metric)| *metric)
+        .map(|(gateway, _)| gateway);
+
+    Ok(gateway)
+}
+
+/// Reads the default routes with a gateway from `/proc/net/route`.
+fn read_ipv4_default_routes() -> Result<Vec<(Gateway, u32)>> {
+    let routes = std::fs::read_to_string("/proc/net/route")?;
+
+    let routes = routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            let [device, destination, gateway, flags, _, _, metric, mask, ..] = columns[..] else {
+                return None;
+            };
+
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            if destination != "00000000" || mask != "00000000" {
+                return None;
+            }
+            if flags & (RTF_UP | RTF_GATEWAY) != RTF_UP | RTF_GATEWAY {
+                return None;
+            }
+
+            // The kernel prints the address in host byte order.
+            let gateway = u32::from_str_radix(gateway, 16).ok()?;
+            let gateway = Gateway {
+                address: IpAddr::V4(Ipv4Addr::from(gateway.to_ne_bytes())),
+                device: Some(device.to_owned()),
+            };
+
+            Some((gateway, metric.parse().ok()?))
+        })
+        .collect();
+
+    Ok(routes)
+}
+
+/// Reads the default routes with a gateway from `/proc/net/ipv6_route`.
+fn read_ipv6_default_routes() -> Result<Vec<(Gateway, u32)>> {
+    let routes = match std::fs::read_to_string("/proc/net/ipv6_route") {
+        Ok(routes) => routes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let routes = routes
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            let [destination, prefix, _, _, next_hop, metric, _, _, flags, device] = columns[..]
+            else {
+                return None;
+            };
+
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            if u128::from_str_radix(destination, 16).ok()? != 0 || prefix != "00" {
+                return None;
+            }
+            if flags & (RTF_UP | RTF_GATEWAY) != RTF_UP | RTF_GATEWAY {
+                return None;
+            }
+
+            let gateway = Gateway {
+                address: IpAddr::V6(Ipv6Addr::from(u128::from_str_radix(next_hop, 16).ok()?)),
+                device: Some(device.to_owned()),
+            };
+
+            Some((gateway, u32::from_str_radix(metric, 16).ok()?))
+        })
+        .collect();
+
+    Ok(routes)
+}
@@ -0,0 +1,105 @@
+use super::{find_default_gateway, VpnInterfaces};
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// Next hop used to escape destinations.
+#[derive(Clone, PartialEq)]
+pub struct Gateway {
+    pub address: IpAddr,
+    pub device: Option<String>,
+}
+
+impl Display for Gateway {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.device {
+            Some(device) => write!(f, "{} dev {}", self.address, device),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
+
+/// Gateway given in the command line.
+#[derive(Clone)]
+pub enum GatewaySetting {
+    /// Use the gateway of the default route that doesn't go through a VPN.
+    Auto,
+    /// Address with an optional device, given as `address%device`. Link-local gateways need it.
+    Address(Box<Gateway>),
+}
+
+impl FromStr for GatewaySetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(GatewaySetting::Auto);
+        }
+
+        let (address, device) = match s.split_once('%') {
+            Some((_, "")) => return Err(format!("Missing device in gateway: {s}")),
+            Some((address, device)) => (address, Some(device.to_owned())),
+            None => (s, None),
+        };
+        let address = address.parse().map_err(|e| format!("{e}: {address}"))?;
+
+        Ok(GatewaySetting::Address(Box::new(Gateway {
+            address,
+            device,
+        })))
+    }
+}
+
+/// Gateways used to escape destinations of each address family.
+#[derive(Clone, Default, PartialEq)]
+pub struct Gateways {
+    pub ipv4: Option<Gateway>,
+    pub ipv6: Option<Gateway>,
+}
+
+impl Gateways {
+    /// Finds the gateways to use for the settings given in the command line.
+    pub fn resolve(
+        ipv4: &GatewaySetting,
+        ipv6: Option<&GatewaySetting>,
+        vpn_interfaces: &VpnInterfaces,
+    ) -> Result<Self> {
+        let ipv4 = match ipv4 {
+            GatewaySetting::Auto => {
+                find_default_gateway(IpAddr::V4(Ipv4Addr::UNSPECIFIED), vpn_interfaces)?
+            }
+            GatewaySetting::Address(gateway) if gateway.address.is_ipv4() => {
+                Some(gateway.as_ref().clone())
+            }
+            GatewaySetting::Address(gateway) => {
+                return Err(eyre!("Invalid IPv4 gateway: {gateway}"));
+            }
+        };
+
+        let ipv6 = match ipv6 {
+            None => None,
+            Some(GatewaySetting::Auto) => {
+                find_default_gateway(IpAddr::V6(Ipv6Addr::UNSPECIFIED), vpn_interfaces)?
+            }
+            Some(GatewaySetting::Address(gateway)) if gateway.address.is_ipv6() => {
+                Some(gateway.as_ref().clone())
+            }
+            Some(GatewaySetting::Address(gateway)) => {
+                return Err(eyre!("Invalid IPv6 gateway: {gateway}"));
+            }
+        };
+
+        Ok(Self { ipv4, ipv6 })
+    }
+
+    /// Returns the gateway of the address family of the destination.
+    pub fn get(&self, destination: &IpAddr) -> Option<&Gateway> {
+        match destination {
+            IpAddr::V4(_) => self.ipv4.as_ref(),
+            IpAddr::V6(_) => self.ipv6.as_ref(),
+        }
+    }
+}
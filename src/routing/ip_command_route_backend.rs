@@ -0,0 +1,338 @@
+use super::{Gateway, Route, RouteBackend, RouteError, Rule};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    process::Command,
+};
+
+/// Route types that don't forward traffic to a gateway.
+const SPECIAL_ROUTE_TYPES: [&str; 9] = [
+    "unreachable",
+    "blackhole",
+    "prohibit",
+    "local",
+    "broadcast",
+    "multicast",
+    "throw",
+    "nat",
+    "anycast",
+];
+
+/// Runs the `ip route` command.
+pub struct IpCommandRouteBackend {
+    table: String,
+}
+
+impl IpCommandRouteBackend {
+    pub fn new(table: u32) -> Self {
+        Self {
+            table: table.to_string(),
+        }
+    }
+
+    fn run(args: &[&str]) -> Result<String, RouteError> {
+        let output = Command::new("ip")
+            .args(args)
+            .output()
+            .map_err(|e| RouteError::Other(format!("fail to run ip: {e}")))?;
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error = if stderr.contains("File exists") {
+            RouteError::AlreadyExists
+        } else if stderr.contains("No such process")
+            || stderr.contains("No such file or directory")
+            || stderr.contains("table does not exist")
+        {
+            RouteError::NotFound
+        } else if stderr.contains("Network is unreachable") || stderr.contains("invalid gateway") {
+            RouteError::NetworkUnreachable
+        } else if stderr.contains("Operation not permitted") {
+            RouteError::PermissionDenied
+        } else {
+            RouteError::Other(stderr.trim().to_owned())
+        };
+
+        Err(error)
+    }
+}
+
+impl RouteBackend for IpCommandRouteBackend {
+    fn name(&self) -> &'static str {
+        "ip"
+    }
+
+    fn add_route(&self, destination: &IpAddr, gateway: &Gateway) -> Result<(), RouteError> {
+        let (family, prefix) = family_and_prefix(destination);
+        let destination = format!("{destination}/{prefix}");
+        let address = gateway.address.to_string();
+
+        let mut args = vec![family, "route", "add", &destination, "via", &address];
+        if let Some(device) = &gateway.device {
+            args.extend(["dev", device]);
+        }
+        args.extend(["table", &self.table]);
+
+        Self::run(&args)?;
+
+        Ok(())
+    }
+
+    fn remove_route(&self, destination: &IpAddr) -> Result<(), RouteError> {
+        let (family, prefix) = family_and_prefix(destination);
+        let destination = format!("{destination}/{prefix}");
+
+        Self::run(&[family, "route", "del", &destination, "table", &self.table])?;
+
+        Ok(())
+    }
+
+    fn list_routes(&self) -> Result<Vec<Route>, RouteError> {
+        let mut routes = Vec::new();
+
+        for family in ["-4", "-6"] {
+            let output = match Self::run(&[family, "route", "show", "table", &self.table]) {
+                Ok(output) => output,
+                Err(RouteError::NotFound) => continue,
+                Err(e) => return Err(e),
+            };
+
+            routes.extend(output.lines().filter_map(|line| parse_route(family, line)));
+        }
+
+        Ok(routes)
+    }
+
+    fn list_rules(&self) -> Result<Vec<Rule>, RouteError> {
+        let output = Self::run(&["-4", "rule", "show"])?;
+
+        Ok(output.lines().filter_map(parse_rule).collect())
+    }
+
+    fn add_rule(&self, priority: u32) -> Result<(), RouteError> {
+        let priority = priority.to_string();
+
+        for family in ["-4", "-6"] {
+            let args = [
+                family,
+                "rule",
+                "add",
+                "lookup",
+                &self.table,
+                "priority",
+                &priority,
+            ];
+            match Self::run(&args) {
+                Ok(_) | Err(RouteError::AlreadyExists) => { /* Do nothing. */ }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_rule(&self, priority: u32) -> Result<(), RouteError> {
+        let priority = priority.to_string();
+
+        for family in ["-4", "-6"] {
+            let args = [
+                family,
+                "rule",
+                "del",
+                "lookup",
+                &self.table,
+                "priority",
+                &priority,
+            ];
+            match Self::run(&args) {
+                Ok(_) | Err(RouteError::NotFound) => { /* Do nothing. */ }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a line printed by `ip route show`.
+fn parse_route(family: &str, line: &str) -> Option<Route> {
+    let mut tokens = line.split_whitespace().peekable();
+    if tokens
+        .peek()
+        .is_some_and(|token| SPECIAL_ROUTE_TYPES.contains(token))
+    {
+        return None;
+    }
+    tokens.next_if_eq(&"unicast");
+
+    let destination = tokens.next()?;
+    let (destination, prefix_length) = match destination {
+        "default" if family == "-4" => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        "default" => (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        destination => match destination.split_once('/') {
+            Some((address, prefix_length)) => (address.parse().ok()?, prefix_length.parse().ok()?),
+            None => {
+                let address: IpAddr = destination.parse().ok()?;
+                let (_, prefix_length) = family_and_prefix(&address);
+
+                (address, prefix_length)
+            }
+        },
+    };
+
+    let gateway = tokens
+        .skip_while(|token| *token != "via")
+        .nth(1)
+        .and_then(|gateway| gateway.parse().ok());
+
+    Some(Route {
+        destination,
+        prefix_length,
+        gateway,
+    })
+}
+
+/// Parses a line printed by `ip rule show`.
+fn parse_rule(line: &str) -> Option<Rule> {
+    let (priority, selector) = line.split_once(':')?;
+
+    let mut rule = Rule {
+        priority: priority.trim().parse().ok()?,
+        table: 0,
+        selective: false,
+        invert: false,
+        fwmark: None,
+        suppress_prefix_length: None,
+    };
+    let mut tokens = selector.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "not" => rule.invert = true,
+            "from" | "to" => rule.selective |= tokens.next()? != "all",
+            "fwmark" => {
+                let fwmark = tokens.next()?.split('/').next()?;
+                rule.fwmark = u32::from_str_radix(fwmark.trim_start_matches("0x"), 16).ok();
+            }
+            "lookup" | "table" => {
+                rule.table = match tokens.next()? {
+                    "local" => 255,
+                    "main" => 254,
+                    "default" => 253,
+                    table => table.parse().ok()?,
+                };
+            }
+            "suppress_prefixlength" => {
+                rule.suppress_prefix_length = tokens.next()?.parse().ok();
+            }
+
+            _ => { /* Do nothing. */ }
+        }
+    }
+
+    Some(rule)
+}
+
+fn family_and_prefix(address: &IpAddr) -> (&'static str, u8) {
+    match address {
+        IpAddr::V4(_) => ("-4", 32),
+        IpAddr::V6(_) => ("-6", 128),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_default_routes() {
+        let route = parse_route(
+            "-4",
+            "unicast default via 192.0.2.1 dev eth0 proto boot scope global",
+        )
+        .unwrap();
+        assert_eq!(route.destination, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(route.prefix_length, 0);
+        assert_eq!(route.gateway, Some(IpAddr::from([192, 0, 2, 1])));
+
+        let route = parse_route(
+            "-6",
+            "default via fe80::1 dev eth0 proto ra metric 1024 expires 1799sec pref medium",
+        )
+        .unwrap();
+        assert_eq!(route.destination, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(route.gateway, Some("fe80::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_prefix_and_host_routes() {
+        let route = parse_route(
+            "-4",
+            "unicast 192.0.2.0/24 dev eth0 proto kernel scope link src 192.0.2.2",
+        )
+        .unwrap();
+        assert_eq!(route.destination, IpAddr::from([192, 0, 2, 0]));
+        assert_eq!(route.prefix_length, 24);
+        assert_eq!(route.gateway, None);
+
+        let route = parse_route("-4", "198.51.100.7 via 192.0.2.1 dev eth0 proto static").unwrap();
+        assert!(route.is_host());
+        assert_eq!(route.gateway, Some(IpAddr::from([192, 0, 2, 1])));
+
+        let route = parse_route(
+            "-6",
+            "2001:db8::5 via fe80::1 dev eth0 metric 1024 pref medium",
+        )
+        .unwrap();
+        assert!(route.is_host());
+    }
+
+    #[test]
+    fn skips_special_routes() {
+        assert!(parse_route("-4", "blackhole 10.99.0.0/16 proto boot scope global").is_none());
+        assert!(parse_route("-4", "unreachable 10.98.0.0/16").is_none());
+        assert!(parse_route(
+            "-4",
+            "local 192.0.2.2 dev eth0 table local proto kernel scope host src 192.0.2.2"
+        )
+        .is_none());
+        assert!(parse_route("-4", "").is_none());
+    }
+
+    #[test]
+    fn parses_main_rule() {
+        let rule = parse_rule("32766:\tfrom all lookup main").unwrap();
+        assert_eq!(rule.priority, 32766);
+        assert_eq!(rule.table, 254);
+        assert!(!rule.selective && !rule.invert);
+        assert_eq!(rule.fwmark, None);
+        assert_eq!(rule.suppress_prefix_length, None);
+    }
+
+    #[test]
+    fn parses_wg_quick_rules() {
+        let rule = parse_rule("32764:\tfrom all lookup main suppress_prefixlength 0").unwrap();
+        assert_eq!(rule.table, 254);
+        assert_eq!(rule.suppress_prefix_length, Some(0));
+
+        let rule = parse_rule("32765:\tnot from all fwmark 0xca6c lookup 51820").unwrap();
+        assert_eq!(rule.priority, 32765);
+        assert_eq!(rule.table, 51820);
+        assert!(rule.invert);
+        assert!(!rule.selective);
+        assert_eq!(rule.fwmark, Some(0xca6c));
+    }
+
+    #[test]
+    fn parses_selective_rules() {
+        let rule = parse_rule("100:\tfrom 10.0.0.0/8 lookup 200").unwrap();
+        assert!(rule.selective);
+        assert_eq!(rule.table, 200);
+
+        let rule = parse_rule("101:\tfrom all fwmark 0x1/0xff lookup default").unwrap();
+        assert_eq!(rule.fwmark, Some(1));
+        assert_eq!(rule.table, 253);
+
+        assert!(parse_rule("not a rule").is_none());
+    }
+}
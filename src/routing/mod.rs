@@ -0,0 +1,17 @@
+mod default_route;
+mod gateways;
+mod ip_command_route_backend;
+mod route_backend;
+mod rtnetlink_route_backend;
+mod vpn_interfaces;
+mod vpn_layout;
+
+pub use default_route::find_default_gateway;
+pub use gateways::{Gateway, GatewaySetting, Gateways};
+pub use ip_command_route_backend::IpCommandRouteBackend;
+pub use route_backend::{
+    create_route_backend, Route, RouteBackend, RouteBackendKind, RouteError, Rule, MAIN_TABLE,
+};
+pub use rtnetlink_route_backend::RtnetlinkRouteBackend;
+pub use vpn_interfaces::VpnInterfaces;
+pub use vpn_layout::{RoutePlacement, VpnLayout};
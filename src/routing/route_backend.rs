@@ -0,0 +1,110 @@
+use super::{Gateway, IpCommandRouteBackend, RtnetlinkRouteBackend};
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use std::{fmt::Display, net::IpAddr};
+
+/// Route read from the kernel routing table.
+pub struct Route {
+    pub destination: IpAddr,
+    pub prefix_length: u8,
+    pub gateway: Option<IpAddr>,
+}
+
+impl Route {
+    pub fn is_host(&self) -> bool {
+        match self.destination {
+            IpAddr::V4(_) => self.prefix_length == 32,
+            IpAddr::V6(_) => self.prefix_length == 128,
+        }
+    }
+}
+
+/// IPv4 policy rule read from the kernel.
+pub struct Rule {
+    pub priority: u32,
+    pub table: u32,
+    /// The rule only matches some sources or destinations.
+    pub selective: bool,
+    pub invert: bool,
+    pub fwmark: Option<u32>,
+    pub suppress_prefix_length: Option<u32>,
+}
+
+/// Installs and removes host routes in the routing table the backend was created for.
+pub trait RouteBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Adds a host route to the destination through the gateway.
+    fn add_route(&self, destination: &IpAddr, gateway: &Gateway) -> Result<(), RouteError>;
+
+    /// Removes the host route to the destination.
+    fn remove_route(&self, destination: &IpAddr) -> Result<(), RouteError>;
+
+    /// Returns every route of the routing table.
+    fn list_routes(&self) -> Result<Vec<Route>, RouteError>;
+
+    /// Adds a policy rule, for both address families, sending all traffic to the routing table.
+    ///
+    /// Rules left over by a previous run are kept.
+    fn add_rule(&self, priority: u32) -> Result<(), RouteError>;
+
+    /// Removes the policy rule added by `add_rule`, for both address families.
+    fn remove_rule(&self, priority: u32) -> Result<(), RouteError>;
+
+    /// Returns every IPv4 policy rule.
+    fn list_rules(&self) -> Result<Vec<Rule>, RouteError>;
+}
+
+#[derive(Debug)]
+pub enum RouteError {
+    AlreadyExists,
+    NotFound,
+    NetworkUnreachable,
+    PermissionDenied,
+    Other(String),
+}
+
+impl RouteError {
+    pub fn from_errno(errno: i32) -> Self {
+        match errno {
+            libc::EEXIST => RouteError::AlreadyExists,
+            libc::ESRCH | libc::ENOENT => RouteError::NotFound,
+            libc::ENETUNREACH => RouteError::NetworkUnreachable,
+            libc::EPERM | libc::EACCES => RouteError::PermissionDenied,
+
+            _ => RouteError::Other(std::io::Error::from_raw_os_error(errno).to_string()),
+        }
+    }
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::AlreadyExists => write!(f, "route already exists"),
+            RouteError::NotFound => write!(f, "route not found"),
+            RouteError::NetworkUnreachable => write!(f, "network is unreachable"),
+            RouteError::PermissionDenied => write!(f, "permission denied"),
+            RouteError::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RouteBackendKind {
+    /// Talk to the kernel through rtnetlink.
+    Netlink,
+    /// Run the `ip route` command.
+    Ip,
+}
+
+/// Identifier of the main routing table.
+pub const MAIN_TABLE: u32 = 254;
+
+pub fn create_route_backend(kind: RouteBackendKind, table: u32) -> Result<Box<dyn RouteBackend>> {
+    match kind {
+        RouteBackendKind::Netlink => Ok(Box::new(RtnetlinkRouteBackend::new(table)?)),
+        RouteBackendKind::Ip => Ok(Box::new(IpCommandRouteBackend::new(table))),
+    }
+}
@@ -0,0 +1,321 @@
+use super::{Gateway, Route, RouteBackend, RouteError, Rule};
+use crate::netlink::{parse_attributes, push_attribute, NetlinkSocket, NLM_F_ACK, NLM_F_DUMP};
+use color_eyre::eyre::Result;
+use std::{
+    ffi::CString,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+};
+
+const RTM_NEWROUTE: u16 = 24;
+const RTM_DELROUTE: u16 = 25;
+const RTM_GETROUTE: u16 = 26;
+const RTM_NEWRULE: u16 = 32;
+const RTM_DELRULE: u16 = 33;
+const RTM_GETRULE: u16 = 34;
+
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_TABLE: u16 = 15;
+
+const FRA_PRIORITY: u16 = 6;
+const FRA_FWMARK: u16 = 10;
+const FRA_SUPPRESS_PREFIXLEN: u16 = 14;
+const FRA_TABLE: u16 = 15;
+const FR_ACT_TO_TBL: u8 = 1;
+const FIB_RULE_INVERT: u32 = 0x2;
+
+const RT_TABLE_UNSPEC: u8 = 0;
+const RTPROT_STATIC: u8 = 4;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RT_SCOPE_NOWHERE: u8 = 255;
+const RTN_UNICAST: u8 = 1;
+
+const RTMSG_LEN: usize = 12;
+
+/// Talks to the kernel through rtnetlink, reporting the errors of every request.
+pub struct RtnetlinkRouteBackend {
+    socket: Mutex<NetlinkSocket>,
+    table: u32,
+}
+
+impl RtnetlinkRouteBackend {
+    pub fn new(table: u32) -> Result<Self> {
+        let socket = NetlinkSocket::open(libc::NETLINK_ROUTE)?;
+
+        Ok(Self {
+            socket: Mutex::new(socket),
+            table,
+        })
+    }
+
+    fn request(
+        &self,
+        message_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<Vec<Vec<u8>>, RouteError> {
+        let Ok(mut socket) = self.socket.lock() else {
+            return Err(RouteError::Other("fail to lock netlink socket".to_owned()));
+        };
+
+        match socket.request(message_type, flags, payload) {
+            Ok(messages) => Ok(messages
+                .into_iter()
+                .map(|message| message.payload)
+                .collect()),
+            Err(e) => Err(match e.raw_os_error() {
+                Some(errno) => RouteError::from_errno(errno),
+                None => RouteError::Other(e.to_string()),
+            }),
+        }
+    }
+
+    /// Builds a `struct rtmsg` for a host route to the destination in our table.
+    fn route_message(
+        &self,
+        destination: &IpAddr,
+        protocol: u8,
+        scope: u8,
+        route_type: u8,
+    ) -> Vec<u8> {
+        let (family, prefix) = family_and_prefix(destination);
+
+        let mut message = vec![
+            family,
+            prefix,
+            0, // Source length.
+            0, // TOS.
+            u8::try_from(self.table).unwrap_or(RT_TABLE_UNSPEC),
+            protocol,
+            scope,
+            route_type,
+        ];
+        message.extend_from_slice(&0u32.to_ne_bytes()); // Flags.
+        push_attribute(&mut message, RTA_TABLE, &self.table.to_ne_bytes());
+
+        message
+    }
+
+    /// Builds a `struct fib_rule_hdr` sending all traffic of the family to our table.
+    fn rule_message(&self, family: u8, priority: u32) -> Vec<u8> {
+        let mut message = vec![
+            family,
+            0, // Destination length.
+            0, // Source length.
+            0, // TOS.
+            u8::try_from(self.table).unwrap_or(RT_TABLE_UNSPEC),
+            0, // Reserved.
+            0, // Reserved.
+            FR_ACT_TO_TBL,
+        ];
+        message.extend_from_slice(&0u32.to_ne_bytes()); // Flags.
+        push_attribute(&mut message, FRA_PRIORITY, &priority.to_ne_bytes());
+        push_attribute(&mut message, FRA_TABLE, &self.table.to_ne_bytes());
+
+        message
+    }
+
+    /// Parses a `struct rtmsg`, keeping only the routes of our table.
+    fn parse_route(&self, message: &[u8]) -> Option<Route> {
+        if message.len() < RTMSG_LEN {
+            return None;
+        }
+        let family = message[0] as i32;
+        let prefix_length = message[1];
+
+        let mut table = message[4] as u32;
+        let mut destination = None;
+        let mut gateway = None;
+        for (attribute_type, data) in parse_attributes(&message[RTMSG_LEN..]) {
+            match attribute_type {
+                RTA_TABLE => table = u32::from_ne_bytes(data.try_into().ok()?),
+                RTA_DST => destination = parse_address(family, data),
+                RTA_GATEWAY => gateway = parse_address(family, data),
+
+                _ => { /* Do nothing. */ }
+            }
+        }
+        if table != self.table {
+            return None;
+        }
+
+        let destination = match (destination, family) {
+            (Some(destination), _) => destination,
+            (None, libc::AF_INET) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (None, _) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        Some(Route {
+            destination,
+            prefix_length,
+            gateway,
+        })
+    }
+}
+
+impl RouteBackend for RtnetlinkRouteBackend {
+    fn name(&self) -> &'static str {
+        "netlink"
+    }
+
+    fn add_route(&self, destination: &IpAddr, gateway: &Gateway) -> Result<(), RouteError> {
+        let mut payload =
+            self.route_message(destination, RTPROT_STATIC, RT_SCOPE_UNIVERSE, RTN_UNICAST);
+        push_attribute(&mut payload, RTA_DST, &address_octets(destination));
+        push_attribute(&mut payload, RTA_GATEWAY, &address_octets(&gateway.address));
+        if let Some(device) = &gateway.device {
+            let index = interface_index(device)?;
+            push_attribute(&mut payload, RTA_OIF, &index.to_ne_bytes());
+        }
+
+        self.request(
+            RTM_NEWROUTE,
+            NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK,
+            &payload,
+        )?;
+
+        Ok(())
+    }
+
+    fn remove_route(&self, destination: &IpAddr) -> Result<(), RouteError> {
+        let mut payload = self.route_message(destination, 0, RT_SCOPE_NOWHERE, 0);
+        push_attribute(&mut payload, RTA_DST, &address_octets(destination));
+
+        self.request(RTM_DELROUTE, NLM_F_ACK, &payload)?;
+
+        Ok(())
+    }
+
+    fn list_routes(&self) -> Result<Vec<Route>, RouteError> {
+        let mut routes = Vec::new();
+
+        for family in [libc::AF_INET as u8, libc::AF_INET6 as u8] {
+            let mut payload = vec![0u8; RTMSG_LEN];
+            payload[0] = family;
+
+            for message in self.request(RTM_GETROUTE, NLM_F_DUMP, &payload)? {
+                if let Some(route) = self.parse_route(&message) {
+                    routes.push(route);
+                }
+            }
+        }
+
+        Ok(routes)
+    }
+
+    fn list_rules(&self) -> Result<Vec<Rule>, RouteError> {
+        let mut payload = vec![0u8; RTMSG_LEN];
+        payload[0] = libc::AF_INET as u8;
+
+        let rules = self
+            .request(RTM_GETRULE, NLM_F_DUMP, &payload)?
+            .iter()
+            .filter_map(|message| parse_rule(message))
+            .collect();
+
+        Ok(rules)
+    }
+
+    fn add_rule(&self, priority: u32) -> Result<(), RouteError> {
+        for family in [libc::AF_INET as u8, libc::AF_INET6 as u8] {
+            let payload = self.rule_message(family, priority);
+            let flags = NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK;
+
+            match self.request(RTM_NEWRULE, flags, &payload) {
+                Ok(_) | Err(RouteError::AlreadyExists) => { /* Do nothing. */ }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_rule(&self, priority: u32) -> Result<(), RouteError> {
+        for family in [libc::AF_INET as u8, libc::AF_INET6 as u8] {
+            let payload = self.rule_message(family, priority);
+
+            match self.request(RTM_DELRULE, NLM_F_ACK, &payload) {
+                Ok(_) | Err(RouteError::NotFound) => { /* Do nothing. */ }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a `struct fib_rule_hdr`.
+fn parse_rule(message: &[u8]) -> Option<Rule> {
+    if message.len() < RTMSG_LEN {
+        return None;
+    }
+    let flags = u32::from_ne_bytes(message[8..12].try_into().ok()?);
+
+    let mut rule = Rule {
+        priority: 0,
+        table: message[4] as u32,
+        selective: message[1] != 0 || message[2] != 0,
+        invert: flags & FIB_RULE_INVERT != 0,
+        fwmark: None,
+        suppress_prefix_length: None,
+    };
+    for (attribute_type, data) in parse_attributes(&message[RTMSG_LEN..]) {
+        let Ok(data) = <[u8; 4]>::try_from(data) else {
+            continue;
+        };
+        let value = u32::from_ne_bytes(data);
+
+        match attribute_type {
+            FRA_PRIORITY => rule.priority = value,
+            FRA_TABLE => rule.table = value,
+            FRA_FWMARK => rule.fwmark = Some(value),
+            // The kernel reports -1 when it isn't set.
+            FRA_SUPPRESS_PREFIXLEN if value != u32::MAX => {
+                rule.suppress_prefix_length = Some(value)
+            }
+
+            _ => { /* Do nothing. */ }
+        }
+    }
+
+    Some(rule)
+}
+
+fn family_and_prefix(address: &IpAddr) -> (u8, u8) {
+    match address {
+        IpAddr::V4(_) => (libc::AF_INET as u8, 32),
+        IpAddr::V6(_) => (libc::AF_INET6 as u8, 128),
+    }
+}
+
+fn parse_address(family: i32, data: &[u8]) -> Option<IpAddr> {
+    match family {
+        libc::AF_INET => Some(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?))),
+        libc::AF_INET6 => Some(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?))),
+
+        _ => None,
+    }
+}
+
+fn interface_index(name: &str) -> Result<u32, RouteError> {
+    let Ok(name) = CString::new(name) else {
+        return Err(RouteError::Other(format!("invalid interface name: {name}")));
+    };
+
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(RouteError::from_errno(libc::ENODEV)),
+        index => Ok(index),
+    }
+}
+
+fn address_octets(address: &IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
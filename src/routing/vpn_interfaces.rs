@@ -0,0 +1,55 @@
+use color_eyre::eyre::Result;
+
+/// Interface name prefixes used by VPN clients.
+const VPN_INTERFACE_PREFIXES: [&str; 3] = ["tun", "wg", "ppp"];
+
+const IFF_UP: u32 = 0x1;
+
+/// Network interfaces that belong to a VPN.
+#[derive(Clone, Default)]
+pub struct VpnInterfaces {
+    names: Vec<String>,
+}
+
+impl VpnInterfaces {
+    /// Interfaces with well known VPN prefixes are always included besides the given names.
+    pub fn new(names: Vec<String>) -> Self {
+        Self { names }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|vpn_name| vpn_name == name)
+            || VPN_INTERFACE_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    }
+
+    /// Returns the VPN interfaces that are currently up.
+    pub fn find_up(&self) -> Result<Vec<String>> {
+        let mut interfaces = Vec::new();
+
+        for entry in std::fs::read_dir("/sys/class/net")? {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if !self.contains(&name) {
+                continue;
+            }
+
+            // Interfaces can disappear while we are iterating them.
+            let Ok(flags) = std::fs::read_to_string(entry.path().join("flags")) else {
+                continue;
+            };
+            let Ok(flags) = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16) else {
+                continue;
+            };
+
+            if flags & IFF_UP != 0 {
+                interfaces.push(name);
+            }
+        }
+
+        Ok(interfaces)
+    }
+}
@@ -0,0 +1,213 @@
+use super::{create_route_backend, RouteBackendKind, MAIN_TABLE};
+use color_eyre::eyre::Result;
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+};
+
+const LOCAL_TABLE: u32 = 255;
+const DEFAULT_TABLE: u32 = 253;
+
+/// Priority of the rule that looks up the main table.
+const MAIN_RULE_PRIORITY: u32 = 32766;
+
+/// Routing table used when the routes can't go in the main table.
+const ESCAPE_TABLE: u32 = 3131;
+
+/// How the active VPN steers traffic into the tunnel.
+#[derive(Clone, Copy, PartialEq)]
+pub enum VpnLayout {
+    /// Any VPN route lives in the main table.
+    MainTable,
+    /// `0.0.0.0/1` and `128.0.0.0/1` routes in the main table, like OpenVPN installs.
+    SplitDefault,
+    /// wg-quick: unmarked traffic goes to the VPN table after the main table is looked up without
+    /// its default route.
+    WgQuick { table: u32, priority: u32 },
+    /// A policy rule sends all traffic to the VPN table before the main table is looked up.
+    PolicyTable { table: u32, priority: u32 },
+}
+
+impl Display for VpnLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VpnLayout::MainTable => write!(f, "main table routes"),
+            VpnLayout::SplitDefault => write!(f, "split default routes"),
+            VpnLayout::WgQuick { table, priority } => {
+                write!(
+                    f,
+                    "wg-quick policy routing (table {table}, priority {priority})"
+                )
+            }
+            VpnLayout::PolicyTable { table, priority } => {
+                write!(f, "policy routing (table {table}, priority {priority})")
+            }
+        }
+    }
+}
+
+impl VpnLayout {
+    /// Finds the VPN layout from the IPv4 policy rules and routing tables.
+    pub fn detect(kind: RouteBackendKind) -> Result<Self> {
+        let main = create_route_backend(kind, MAIN_TABLE)?;
+        let rules = main.list_rules()?;
+
+        let main_priority = rules
+            .iter()
+            .filter(|rule| rule.table == MAIN_TABLE && !rule.selective && !rule.invert)
+            .filter(|rule| rule.fwmark.is_none() && rule.suppress_prefix_length.is_none())
+            .map(|rule| rule.priority)
+            .min()
+            .unwrap_or(MAIN_RULE_PRIORITY);
+
+        // Find the first rule, looked up before the main table, that leads to a default route.
+        let mut candidates: Vec<_> = rules
+            .iter()
+            .filter(|rule| ![LOCAL_TABLE, MAIN_TABLE, DEFAULT_TABLE].contains(&rule.table))
+            .filter(|rule| !rule.selective && rule.priority < main_priority)
+            .collect();
+        candidates.sort_by_key(|rule| rule.priority);
+
+        for rule in candidates {
+            let routes = create_route_backend(kind, rule.table)?.list_routes()?;
+            if !routes
+                .iter()
+                .any(|route| route.destination.is_ipv4() && route.prefix_length <= 1)
+            {
+                continue;
+            }
+
+            let suppressed_main = rules.iter().any(|other| {
+                other.table == MAIN_TABLE
+                    && other.suppress_prefix_length == Some(0)
+                    && other.priority < rule.priority
+            });
+            if rule.fwmark.is_some() && rule.invert && suppressed_main {
+                return Ok(VpnLayout::WgQuick {
+                    table: rule.table,
+                    priority: rule.priority,
+                });
+            }
+
+            return Ok(VpnLayout::PolicyTable {
+                table: rule.table,
+                priority: rule.priority,
+            });
+        }
+
+        let routes = main.list_routes()?;
+        let split_default = [Ipv4Addr::UNSPECIFIED, Ipv4Addr::new(128, 0, 0, 0)]
+            .into_iter()
+            .all(|destination| {
+                routes.iter().any(|route| {
+                    route.prefix_length == 1 && route.destination == IpAddr::V4(destination)
+                })
+            });
+        if split_default {
+            return Ok(VpnLayout::SplitDefault);
+        }
+
+        Ok(VpnLayout::MainTable)
+    }
+
+    /// Returns the rule priority below which escape rules are looked up before the VPN, when host
+    /// routes in the main table aren't enough.
+    fn rule_priority_limit(&self) -> Option<u32> {
+        match self {
+            VpnLayout::MainTable | VpnLayout::SplitDefault => None,
+            VpnLayout::WgQuick { priority, .. } | VpnLayout::PolicyTable { priority, .. } => {
+                Some(*priority)
+            }
+        }
+    }
+}
+
+/// Where escape routes are installed.
+#[derive(Clone, Copy, PartialEq)]
+pub struct RoutePlacement {
+    /// Dedicated routing table, the main table is used when not set.
+    pub table: Option<u32>,
+    pub rule_priority: u32,
+}
+
+impl Display for RoutePlacement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.table {
+            Some(table) => write!(f, "table {table} with rule priority {}", self.rule_priority),
+            None => write!(f, "main table"),
+        }
+    }
+}
+
+impl RoutePlacement {
+    /// Adjusts the requested placement so the escape routes take precedence over the VPN routes.
+    pub fn select(layout: &VpnLayout, table: Option<u32>, rule_priority: u32) -> Self {
+        let mut placement = Self {
+            table,
+            rule_priority,
+        };
+
+        // Host routes in the main table win over split and wg-quick routes, but not over a
+        // policy rule looked up before the main table.
+        if let VpnLayout::PolicyTable { .. } = layout {
+            placement.table.get_or_insert(ESCAPE_TABLE);
+        }
+
+        if let (Some(_), Some(limit)) = (placement.table, layout.rule_priority_limit()) {
+            if placement.rule_priority >= limit {
+                placement.rule_priority = limit.saturating_sub(1);
+            }
+        }
+
+        placement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WG_QUICK: VpnLayout = VpnLayout::WgQuick {
+        table: 51820,
+        priority: 32765,
+    };
+    const POLICY_TABLE: VpnLayout = VpnLayout::PolicyTable {
+        table: 200,
+        priority: 100,
+    };
+
+    #[test]
+    fn keeps_main_table_for_main_and_split_layouts() {
+        for layout in [VpnLayout::MainTable, VpnLayout::SplitDefault, WG_QUICK] {
+            let placement = RoutePlacement::select(&layout, None, 1000);
+            assert_eq!(placement.table, None);
+            assert_eq!(placement.rule_priority, 1000);
+        }
+
+        let placement = RoutePlacement::select(&VpnLayout::SplitDefault, Some(10), 1000);
+        assert_eq!(placement.table, Some(10));
+        assert_eq!(placement.rule_priority, 1000);
+    }
+
+    #[test]
+    fn moves_routes_before_policy_table() {
+        let placement = RoutePlacement::select(&POLICY_TABLE, None, 1000);
+        assert_eq!(placement.table, Some(ESCAPE_TABLE));
+        assert_eq!(placement.rule_priority, 99);
+
+        // A rule already looked up before the VPN is kept.
+        let placement = RoutePlacement::select(&POLICY_TABLE, Some(10), 50);
+        assert_eq!(placement.table, Some(10));
+        assert_eq!(placement.rule_priority, 50);
+    }
+
+    #[test]
+    fn moves_dedicated_table_before_wg_quick() {
+        let placement = RoutePlacement::select(&WG_QUICK, Some(10), 40000);
+        assert_eq!(placement.table, Some(10));
+        assert_eq!(placement.rule_priority, 32764);
+
+        let placement = RoutePlacement::select(&WG_QUICK, Some(10), 1000);
+        assert_eq!(placement.rule_priority, 1000);
+    }
+}
@@ -1,33 +1,167 @@
 use crate::{
-    connections::{get_connection_mananger, Connection, ConnectionState},
-    get_service_address_file,
-    messages::{deserialize_from, serialize_to, AttachError, DetachError, Message},
-    monitoring::{TcpConnectionInfo, TcpConnectionStatus},
-    process_manager::{add_process, remove_process_and_trigger_exit},
+    connections::{get_connection_mananger, Connection, ConnectionState, ProbeResult},
+    control_socket::{
+        bind_control_socket, get_peer_credentials, is_peer_gone, PeerCredentials, SocketSettings,
+    },
+    events::{publish, subscribe},
+    get_service_socket_file,
+    messages::{
+        deserialize_request_from, serialize_to, AttachError, DestinationState, DestinationStatus,
+        DetachError, Event, Hello, Message, ProcessStatus, PurgeError, PROTOCOL_VERSION,
+    },
+    monitoring::{
+        get_command_line, get_process_groups, get_process_start_time, get_process_tree,
+        get_process_uid, get_socket_inodes, probe_reachability, ConnectionSource,
+        TcpConnectionInfo, TcpConnectionStatus,
+    },
+    process_manager::{
+        add_process, list_processes, reap_process, remove_process_and_trigger_exit,
+        set_join_handler, set_tracked_children, ProcessInfo,
+    },
+    routing::{
+        create_route_backend, Gateway, GatewaySetting, Gateways, RouteBackend, RouteBackendKind,
+        RouteError, RoutePlacement, VpnInterfaces, VpnLayout, MAIN_TABLE,
+    },
 };
 use color_eyre::eyre::Result;
 use std::{
-    net::{Ipv4Addr, TcpListener, TcpStream},
-    process::Command,
-    sync::mpsc::{channel, Receiver, TryRecvError},
-    time::{Duration, Instant},
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-pub fn service(address: &str, gateway: &str, pooling_rate: Duration) {
-    // Register service port.
-    let port_file_name = get_service_address_file();
+/// Time between checks that a client watching events is still there.
+const WATCH_HANGUP_CHECK: Duration = Duration::from_secs(1);
+
+/// Time a client is given to send its request or to read a response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// First backoff before retrying an address whose route failed.
+const FAILED_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Longest backoff before retrying an address whose route failed.
+const FAILED_MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Where escape routes are installed, moved when a VPN comes up with another layout.
+pub struct RouteTarget {
+    pub backend: Box<dyn RouteBackend>,
+    pub placement: RoutePlacement,
+}
+
+impl RouteTarget {
+    fn add_rule(&self) {
+        let Some(table) = self.placement.table else {
+            return;
+        };
+
+        if let Err(e) = self.backend.add_rule(self.placement.rule_priority) {
+            log::error!("Fail to add rule for routing table {table}: {e}");
+        }
+    }
+
+    fn remove_rule(&self) {
+        let Some(table) = self.placement.table else {
+            return;
+        };
+
+        if let Err(e) = self.backend.remove_rule(self.placement.rule_priority) {
+            log::error!("Fail to remove rule for routing table {table}: {e}");
+        }
+    }
+}
+
+pub struct ServiceConfig {
+    pub gateway: GatewaySetting,
+    pub gateway6: Option<GatewaySetting>,
+    pub gateways: RwLock<Gateways>,
+    pub vpn_interfaces: VpnInterfaces,
+    /// Set while the VPN is down and the routes are suspended.
+    pub vpn_down: AtomicBool,
+    /// Time given to a connection to be established once its route is added.
+    pub verify_window: Option<Duration>,
+    /// Time given to a probe through the physical interface before escaping a destination.
+    pub probe_timeout: Option<Duration>,
+    /// Time after which an address that no tracked process uses is removed.
+    pub lease: Option<Duration>,
+    /// Addresses that never expire.
+    pub pinned: Vec<IpAddr>,
+    /// Remove the addresses that only a process used once it exits or is detached.
+    pub drop_routes: bool,
+    pub pooling_rate: Duration,
+    pub connection_source: Box<dyn ConnectionSource>,
+    pub route_target: RwLock<RouteTarget>,
+    pub route_backend_kind: RouteBackendKind,
+    /// Placement asked for, adjusted to the layout of each VPN that comes up.
+    pub requested_placement: RoutePlacement,
+    /// Group whose members can attach to and detach from processes of other users.
+    pub admin_group: Option<u32>,
+}
+
+pub fn service(socket: SocketSettings, config: ServiceConfig) {
+    // Take the socket first, so a second service doesn't touch the routes of the running one.
+    let socket_file_name = get_service_socket_file();
     log::info!(
-        "Registering service port in: {}",
-        port_file_name.to_str().unwrap()
+        "Starting service in socket {}...",
+        socket_file_name.to_string_lossy()
     );
-    let port = address.split(':').nth(1).unwrap();
-    std::fs::write(&port_file_name, port).expect("Fail to register service port.");
+    let listener =
+        bind_control_socket(&socket_file_name, &socket).expect("Fail to listen in socket");
+
+    log::info!(
+        "Using {} connection source.",
+        config.connection_source.name()
+    );
+    if let Ok(route_target) = config.route_target.read() {
+        log::info!("Using {} route backend.", route_target.backend.name());
+        route_target.add_rule();
+    }
+    if let Ok(gateways) = config.gateways.read() {
+        for gateway in [&gateways.ipv4, &gateways.ipv6].into_iter().flatten() {
+            log::info!("Using gateway: {gateway}");
+        }
+        let without_device = [&gateways.ipv4, &gateways.ipv6]
+            .into_iter()
+            .flatten()
+            .any(|gateway| gateway.device.is_none());
+        if config.probe_timeout.is_some() && without_device {
+            log::warn!("Probes aren't bound to an interface for gateways without a device.");
+        }
+        if gateways.ipv4.is_none() {
+            log::warn!("No IPv4 gateway found, IPv4 destinations will be ignored.");
+        }
+    }
+    reconcile_routes(&config);
+    let config = Arc::new(config);
+
+    // Stop sending traffic to our table once the service is gone.
+    {
+        let config = config.clone();
+        ctrlc::set_handler(move || {
+            log::info!("Stopping service...");
+            save_connections();
+            if let Ok(route_target) = config.route_target.read() {
+                route_target.remove_rule();
+            }
+
+            std::process::exit(0);
+        })
+        .expect("Fail to handle termination signals");
+    }
 
-    // Start listening for client connections.
-    log::info!("Starting service in address {address}...");
-    let listener = TcpListener::bind(address).expect("Fail to listen in address: {address}");
+    // Follow gateway and VPN changes.
+    {
+        let config = config.clone();
+        std::thread::spawn(move || monitor_network(&config));
+    }
 
-    // Handle client requests.
+    // Handle each client in its own thread, so a slow client can't hold up the others.
     for stream in listener.incoming() {
         let stream = match stream {
             Ok(stream) => stream,
@@ -38,83 +172,334 @@ pub fn service(address: &str, gateway: &str, pooling_rate: Duration) {
                 continue;
             }
         };
-        match stream.set_nodelay(true) {
-            Ok(_) => { /* Do nothing. */ }
-            Err(e) => log::error!("Fail to set nodelay: {e}"),
+
+        let config = config.clone();
+        std::thread::spawn(move || handle_client(stream, config));
+    }
+}
+
+fn handle_client(stream: UnixStream, config: Arc<ServiceConfig>) {
+    // Give up on clients that stop reading or writing halfway through a message.
+    let timeouts = stream
+        .set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)));
+    if let Err(e) = timeouts {
+        log::error!("Fail to set client timeouts: {e}");
+
+        return;
+    }
+
+    let credentials = match get_peer_credentials(&stream) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            log::error!("Fail to read client credentials: {e}");
+
+            return;
+        }
+    };
+    log::debug!(
+        "Client connected: PID {} (UID: {}, GID: {})",
+        credentials.pid,
+        credentials.uid,
+        credentials.gid
+    );
+
+    // Exchange versions before anything else.
+    let hello = match deserialize_request_from::<Hello, _>(&stream) {
+        Ok(hello) => hello,
+        Err(e) => {
+            log::error!("Fail to decode hello: {e}");
+
+            return;
         }
+    };
+    if let Err(e) = serialize_to(&Hello::current(), &stream) {
+        log::error!("Fail to send hello: {e}");
 
-        // Decode request message.
-        match deserialize_from::<Message, _>(&stream) {
-            Ok(Message::AttachRequest { pid, delay }) => {
-                attach(pid, delay, pooling_rate, gateway.to_owned(), stream)
-            }
-            Ok(Message::DetachRequest { pid }) => detach(pid, stream),
-            Ok(Message::PurgeRequest) => purge(stream),
+        return;
+    }
+    if hello.version != PROTOCOL_VERSION {
+        log::warn!(
+            "Client speaks protocol version {} instead of {PROTOCOL_VERSION}.",
+            hello.version
+        );
 
-            Ok(_) => {
-                log::error!("Invalid message received.");
+        return;
+    }
 
-                continue;
-            }
+    // Decode request message.
+    match deserialize_request_from::<Message, _>(&stream) {
+        Ok(Message::AttachRequest {
+            pid,
+            delay,
+            children,
+        }) => {
+            // Identify the process before checking its owner, so a reused PID is noticed.
+            let start_time = match get_process_start_time(pid) {
+                Ok(start_time) => start_time,
+                Err(e) => {
+                    log::error!("Fail to find start time of PID: {pid} with error: {e}");
+                    send_attach_response(AttachError::ProcessNotFound, &stream);
+
+                    return;
+                }
+            };
 
+            match is_authorized(&credentials, pid, &config) {
+                Ok(true) => attach(pid, start_time, delay, children, config.clone(), stream),
+                Ok(false) => {
+                    log::warn!(
+                        "UID {} isn't allowed to attach to PID: {pid}",
+                        credentials.uid
+                    );
+                    send_attach_response(AttachError::PermissionDenied, &stream);
+                }
+                Err(e) => {
+                    log::error!("Fail to find owner of PID: {pid} with error: {e}");
+                    send_attach_response(AttachError::ProcessNotFound, &stream);
+                }
+            }
+        }
+        Ok(Message::DetachRequest { pid }) => match is_authorized(&credentials, pid, &config) {
+            Ok(true) => detach(pid, &config, stream),
+            Ok(false) => {
+                log::warn!(
+                    "UID {} isn't allowed to detach from PID: {pid}",
+                    credentials.uid
+                );
+                send_detach_response(DetachError::PermissionDenied, &stream);
+            }
+            Err(e) => {
+                log::error!("Fail to find owner of PID: {pid} with error: {e}");
+                send_detach_response(DetachError::ProcessNotFound, &stream);
+            }
+        },
+        Ok(Message::PurgeRequest) => match is_admin(&credentials, &config) {
+            Ok(true) => purge(&config, stream),
+            Ok(false) => {
+                log::warn!("UID {} isn't allowed to purge connections", credentials.uid);
+                send_purge_response(PurgeError::PermissionDenied, &stream);
+            }
             Err(e) => {
-                log::error!("Fail to decode request: {e}");
+                log::error!("Fail to find groups of client: {e}");
+                send_purge_response(PurgeError::UnknownError, &stream);
+            }
+        },
+        Ok(Message::StatusRequest) => status(stream),
+        Ok(Message::WatchRequest { pid, address }) => watch(pid, address, stream),
 
-                continue;
+        Ok(_) => log::error!("Invalid message received."),
+
+        Err(e) => log::error!("Fail to decode request: {e}"),
+    }
+}
+
+/// Matches the persisted connections against the live routing table: routes that survived are
+/// adopted and the missing ones are added again, for addresses that were escaped before.
+fn reconcile_routes(config: &ServiceConfig) {
+    let Ok(route_target) = config.route_target.read() else {
+        log::error!("Fail to lock route target.");
+
+        return;
+    };
+    let routes = route_target.backend.list_routes();
+    drop(route_target);
+
+    let routes = match routes {
+        Ok(routes) => routes,
+        Err(e) => {
+            log::error!("Fail to list routes: {e}");
+
+            return;
+        }
+    };
+    let routes: Vec<_> = routes.into_iter().filter(|route| route.is_host()).collect();
+
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
+
+        return;
+    };
+
+    for connection in connection_manager.iter_mut() {
+        let address = *connection.address();
+
+        // Pending addresses still wait for their delay, failed ones stay out.
+        let restore = match connection.state() {
+            ConnectionState::InRoutingTable { .. }
+            | ConnectionState::Verified
+            | ConnectionState::Suspended => true,
+            ConnectionState::Pending { .. } | ConnectionState::Failed { .. } => false,
+        };
+        if !restore {
+            continue;
+        }
+
+        let Some(gateway) = get_gateway(&address, config) else {
+            continue;
+        };
+
+        // Only adopt routes through our gateway, not ones through the VPN or a stale gateway.
+        match routes.iter().find(|route| route.destination == address) {
+            Some(route) if route.gateway == Some(gateway.address) => {
+                log::info!("Address {address} already in routing table.");
+                connection.set_gateway(Some(gateway.address));
             }
+            route => {
+                if let Some(route) = route {
+                    log::info!(
+                        "Replacing route to {address} through {}.",
+                        route
+                            .gateway
+                            .map(|gateway| gateway.to_string())
+                            .unwrap_or_else(|| "no gateway".to_owned())
+                    );
+                    remove_ip_from_routing_table(&address, config);
+                }
+                if !add_to_routing_table(connection, config) {
+                    continue;
+                }
+
+                log::info!("Address {address} restored to routing table.");
+            }
+        }
+
+        connection.set_state(ConnectionState::InRoutingTable {
+            start_time: Instant::now(),
+        });
+    }
+
+    // Report host routes through our gateways that we don't know about.
+    for route in routes {
+        if connection_manager
+            .get_connection_mut(&route.destination)
+            .is_some()
+        {
+            continue;
+        }
+
+        let Some(gateway) = get_gateway(&route.destination, config) else {
+            continue;
+        };
+        if route.gateway == Some(gateway.address) {
+            log::warn!(
+                "Route to {} goes through {} but isn't a known connection.",
+                route.destination,
+                gateway.address
+            );
         }
     }
 }
 
-fn attach(pid: u32, delay: u32, pooling_rate: Duration, gateway: String, stream: TcpStream) {
-    log::info!("Attaching to PID: {} with delay of {} ms...", pid, delay);
+/// Root and the members of the admin group can manage every process, other users only their own.
+/// Root and members of the admin group manage every process and destination.
+fn is_admin(credentials: &PeerCredentials, config: &ServiceConfig) -> Result<bool> {
+    if credentials.uid == 0 {
+        return Ok(true);
+    }
+    let Some(admin_group) = config.admin_group else {
+        return Ok(false);
+    };
+
+    Ok(credentials.gid == admin_group
+        || get_process_groups(credentials.pid)?.contains(&admin_group))
+}
+
+fn is_authorized(credentials: &PeerCredentials, pid: u32, config: &ServiceConfig) -> Result<bool> {
+    if is_admin(credentials, config)? {
+        return Ok(true);
+    }
+
+    Ok(get_process_uid(pid)? == credentials.uid)
+}
 
+fn attach(
+    pid: u32,
+    start_time: u64,
+    delay: u32,
+    children: bool,
+    config: Arc<ServiceConfig>,
+    stream: UnixStream,
+) {
+    log::info!(
+        "Attaching to PID: {} with delay of {} ms (children: {})...",
+        pid,
+        delay,
+        children
+    );
+
+    // The process checked for permission must still be the one behind the PID.
+    if get_process_start_time(pid).ok() != Some(start_time) {
+        log::warn!("PID {pid} exited or was reused before attaching.");
+        send_attach_response(AttachError::ProcessNotFound, &stream);
+
+        return;
+    }
+    let info = ProcessInfo {
+        pid,
+        command_line: get_command_line(pid).unwrap_or_default(),
+        delay,
+        attach_time: SystemTime::now(),
+        children,
+        tracked_children: Vec::new(),
+        start_time,
+    };
     let (sender, receiver) = channel();
 
+    // Attaching again replaces the previous settings.
+    let token = match add_process(info.clone(), sender) {
+        Ok(token) => token,
+        Err(e) => {
+            log::error!("Fail to register process: {e}");
+            send_attach_response(AttachError::ProcessNotFound, &stream);
+
+            return;
+        }
+    };
+
     let join_handle = std::thread::spawn(move || {
-        let delay = Duration::from_millis(delay as u64);
+        track_process(&info, token, &config, stream, receiver);
 
-        track_process(pid, delay, pooling_rate, &gateway, stream, receiver);
+        // Detaching or attaching again already removed this attachment otherwise.
+        match reap_process(pid, token) {
+            Ok(true) if config.drop_routes => drop_process_routes(pid, &config),
+            Ok(_) => { /* Do nothing. */ }
+            Err(e) => log::error!("Fail to reap PID: {pid} with error: {e}"),
+        }
     });
 
-    if add_process(pid, join_handle, sender).is_err() {
-        log::error!("Fail to register process.");
+    if let Err(e) = set_join_handler(pid, token, join_handle) {
+        log::error!("Fail to register process: {e}");
     }
 }
 
-fn detach(pid: u32, stream: TcpStream) {
+fn detach(pid: u32, config: &ServiceConfig, stream: UnixStream) {
     log::info!("Detaching from PID: {pid}...");
 
     match remove_process_and_trigger_exit(pid) {
         Ok(true) => {
             log::info!("Successfuly detach from process: {pid}");
+            publish(Event::Detached { pid });
+            send_detach_response(DetachError::Ok, &stream);
 
-            let msg = Message::DetachResponse {
-                error: DetachError::Ok,
-            };
-            serialize_to(&msg, &stream).expect("Fail to send message to client");
+            if config.drop_routes {
+                drop_process_routes(pid, config);
+            }
         }
         Ok(false) => {
             log::warn!("Fail to detach from process: {pid}");
-
-            let msg = Message::DetachResponse {
-                error: DetachError::ProcessNotFound,
-            };
-            serialize_to(&msg, &stream).expect("Fail to send message to client");
+            send_detach_response(DetachError::ProcessNotFound, &stream);
         }
 
         Err(e) => {
             log::error!("Fail to detach from process: {pid} with error: {e}");
-
-            let msg = Message::DetachResponse {
-                error: DetachError::UnknownError,
-            };
-            serialize_to(&msg, &stream).expect("Fail to send message to client");
+            send_detach_response(DetachError::UnknownError, &stream);
         }
     }
 }
 
-fn purge(stream: TcpStream) {
+fn purge(config: &ServiceConfig, stream: UnixStream) {
     log::info!("Purging connections...");
 
     let connection_manager = get_connection_mananger();
@@ -124,38 +509,243 @@ fn purge(stream: TcpStream) {
         return;
     };
 
-    for connection in connection_manager.iter() {
-        remove_ip_from_routing_table(connection.address());
+    let Ok(route_target) = config.route_target.read() else {
+        log::error!("Fail to lock route target.");
+
+        return;
+    };
+    let table = route_target.placement.table;
+    let routes = route_target.backend.list_routes();
+    drop(route_target);
+
+    let addresses: Vec<_> = match table {
+        // The table is ours, flush it entirely.
+        Some(table) => match routes {
+            Ok(routes) => routes
+                .iter()
+                .filter(|route| route.is_host())
+                .map(|route| route.destination)
+                .collect(),
+            Err(e) => {
+                log::error!("Fail to list routes of table {table}: {e}");
+
+                Vec::new()
+            }
+        },
+
+        None => connection_manager
+            .iter()
+            .map(|connection| *connection.address())
+            .collect(),
+    };
+    for address in addresses.iter() {
+        remove_ip_from_routing_table(address, config);
     }
 
     connection_manager.purge();
+    publish(Event::Purged);
+
+    // Send response to client.
+    let msg = Message::PurgeResponse {
+        error: PurgeError::Ok,
+        addresses,
+    };
+    send_response(&msg, &stream);
+}
+
+fn status(stream: UnixStream) {
+    let processes = match list_processes() {
+        Ok(processes) => processes,
+        Err(e) => {
+            log::error!("Fail to list processes: {e}");
+
+            Vec::new()
+        }
+    };
+    let processes = processes
+        .into_iter()
+        .map(|info| ProcessStatus {
+            pid: info.pid,
+            command_line: info.command_line,
+            delay: info.delay,
+            attach_time: info
+                .attach_time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            children: info.children,
+            tracked_children: info.tracked_children,
+        })
+        .collect();
+
+    let destinations = {
+        let connection_manager = get_connection_mananger();
+        let Ok(connection_manager) = connection_manager.lock() else {
+            log::error!("Fail to lock connection manager.");
+
+            return;
+        };
+
+        connection_manager
+            .iter()
+            .map(|connection| {
+                let (state, start_time) = match connection.state() {
+                    ConnectionState::Pending { start_time } => {
+                        (DestinationState::Pending, Some(start_time))
+                    }
+                    ConnectionState::InRoutingTable { start_time } => {
+                        (DestinationState::InRoutingTable, Some(start_time))
+                    }
+                    ConnectionState::Verified => (DestinationState::Verified, None),
+                    ConnectionState::Failed { start_time } => {
+                        (DestinationState::Failed, Some(start_time))
+                    }
+                    ConnectionState::Suspended => (DestinationState::Suspended, None),
+                };
+
+                DestinationStatus {
+                    address: *connection.address(),
+                    state,
+                    state_duration: start_time
+                        .map(|start_time| start_time.elapsed().as_millis() as u64),
+                    pid: connection.pid(),
+                }
+            })
+            .collect()
+    };
 
     // Send response to client.
-    let msg = Message::PurgeResponse;
-    serialize_to(&msg, &stream).expect("Fail to send message to client");
+    let msg = Message::StatusResponse {
+        processes,
+        destinations,
+    };
+    send_response(&msg, &stream);
+}
+
+fn watch(pid: Option<u32>, address: Option<IpAddr>, stream: UnixStream) {
+    let receiver = match subscribe() {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            log::error!("Fail to subscribe to events: {e}");
+
+            return;
+        }
+    };
+    log::info!("Client watching events.");
+
+    // Stream events until the client goes away, or stops reading them.
+    loop {
+        let event = match receiver.recv_timeout(WATCH_HANGUP_CHECK) {
+            Ok(event) => event,
+
+            // Nothing happened, make sure the client is still there.
+            Err(RecvTimeoutError::Timeout) if is_peer_gone(&stream) => break,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if !event.matches(pid, address) {
+            continue;
+        }
+
+        if serialize_to(&Message::Event { event }, &stream).is_err() {
+            break;
+        }
+    }
+    log::info!("Client stopped watching events.");
 }
 
 fn track_process(
-    pid: u32,
-    delay: Duration,
-    pooling_rate: Duration,
-    gateway: &str,
-    stream: TcpStream,
+    info: &ProcessInfo,
+    token: u64,
+    config: &ServiceConfig,
+    stream: UnixStream,
     exit_receiver: Receiver<()>,
 ) {
+    let ProcessInfo {
+        pid,
+        children,
+        start_time,
+        ..
+    } = *info;
+    let delay = Duration::from_millis(info.delay as u64);
     let mut response_sent = false;
-    loop {
+    let mut tracked_children = HashSet::new();
+    'tracking: loop {
         // Check if we should start cleaning up.
         match exit_receiver.try_recv() {
             Ok(_) => break,
 
-            Err(TryRecvError::Disconnected) => unreachable!(),
+            // Another attachment replaced this one in the registry.
+            Err(TryRecvError::Disconnected) => break,
             Err(TryRecvError::Empty) => { /* Do nothing. */ }
         }
 
         {
-            let connections_pending = match get_ipv4_pending_connections_from_pid(pid) {
-                Ok(connections_pending) => {
+            // Only a missing process, or a different start time after its PID was reused, means
+            // the process exited.
+            match get_process_start_time(pid) {
+                Ok(current) if current == start_time => { /* Do nothing. */ }
+                Err(e) if Path::new(&format!("/proc/{pid}")).exists() => {
+                    log::warn!("Fail to read start time of PID: {pid} with error: {e}");
+
+                    std::thread::sleep(config.pooling_rate);
+                    continue;
+                }
+                result => {
+                    if let Err(e) = result {
+                        log::debug!("Process {pid} lookup failed with error: {e}");
+                    }
+
+                    if response_sent {
+                        log::info!("Process {pid} exited.");
+                        publish(Event::ProcessExited { pid });
+                    } else {
+                        log::error!("Process {pid} exited before attaching.");
+                        send_attach_response(AttachError::ProcessNotFound, &stream);
+                    }
+
+                    return;
+                }
+            }
+            let pids = if children {
+                get_process_tree(pid)
+            } else {
+                Ok(vec![pid])
+            };
+
+            let connections = match pids.and_then(|pids| {
+                let previous_count = tracked_children.len();
+                let mut changed = false;
+                for child in pids.iter().skip(1) {
+                    if tracked_children.insert(*child) {
+                        log::info!("Tracking child process {child} of PID: {pid}");
+                        changed = true;
+                    }
+                }
+                tracked_children.retain(|child| pids.contains(child));
+
+                // Publish the descendants for the status command when they change.
+                if changed || tracked_children.len() != previous_count {
+                    let mut children: Vec<_> = tracked_children.iter().copied().collect();
+                    children.sort_unstable();
+                    if let Err(e) = set_tracked_children(pid, token, children) {
+                        log::error!("Fail to record child processes of PID: {pid} with error: {e}");
+                    }
+                }
+
+                // Established connections are only needed to verify routes and renew leases.
+                let states: &[_] = if config.verify_window.is_some() || config.lease.is_some() {
+                    &[
+                        TcpConnectionStatus::SynSent,
+                        TcpConnectionStatus::Established,
+                    ]
+                } else {
+                    &[TcpConnectionStatus::SynSent]
+                };
+
+                get_connection_info_from_pids(config.connection_source.as_ref(), &pids, states)
+            }) {
+                Ok(connections) => {
                     if !response_sent {
                         log::info!("Successfuly attached to process: {pid}");
                         send_attach_response(AttachError::Ok, &stream);
@@ -163,18 +753,25 @@ fn track_process(
                         response_sent = true;
                     }
 
-                    connections_pending
+                    connections
                 }
-                Err(e) => {
-                    if !response_sent {
-                        log::error!("Unable to find pending connections: {e}");
-                        send_attach_response(AttachError::ProcessNotFound, &stream);
-                    }
+                Err(e) if !response_sent => {
+                    log::error!("Unable to find pending connections: {e}");
+                    send_attach_response(AttachError::ProcessNotFound, &stream);
 
                     return;
                 }
+
+                // The process is still there, try again on the next tick.
+                Err(e) => {
+                    log::warn!("Fail to find connections of PID: {pid} with error: {e}");
+
+                    std::thread::sleep(config.pooling_rate);
+                    continue;
+                }
             };
 
+            let mut probes = Vec::new();
             {
                 // Lock connection manager.
                 let connection_manager = get_connection_mananger();
@@ -185,14 +782,79 @@ fn track_process(
                 };
 
                 // Add new connections.
-                for address in &connections_pending {
-                    if connection_manager.get_connection_mut(address).is_none() {
-                        connection_manager.add_connection(Connection::new(
-                            *address,
-                            ConnectionState::Pending {
-                                start_time: Instant::now(),
-                            },
-                        ));
+                let connections_pending = connections
+                    .iter()
+                    .filter(|connection| connection.status() == &TcpConnectionStatus::SynSent);
+                for connection_info in connections_pending {
+                    let address = connection_info.remote_address();
+                    let Some(gateway) = get_gateway(address, config) else {
+                        continue;
+                    };
+
+                    let port = connection_info.remote_port();
+                    match connection_manager.get_connection_mut(address) {
+                        Some(connection) => {
+                            if connection.add_remote_port(port) {
+                                publish(Event::ConnectionSeen {
+                                    pid,
+                                    address: *address,
+                                    port,
+                                });
+                            }
+
+                            // Give failed addresses another chance once their backoff is over.
+                            if let ConnectionState::Failed { start_time } = connection.state() {
+                                if start_time.elapsed() < get_retry_delay(connection.failures()) {
+                                    continue;
+                                }
+                                log::info!(
+                                    "Retrying address {address} after {} failures.",
+                                    connection.failures()
+                                );
+
+                                connection.set_state(ConnectionState::Pending {
+                                    start_time: Instant::now(),
+                                });
+                                publish(Event::Pending {
+                                    pid: Some(pid),
+                                    address: *address,
+                                });
+                            }
+                        }
+                        None => {
+                            let mut connection = Connection::new(
+                                *address,
+                                ConnectionState::Pending {
+                                    start_time: Instant::now(),
+                                },
+                                Some(pid),
+                            );
+                            connection.add_remote_port(port);
+                            connection.set_gateway(Some(gateway.address));
+                            connection.set_executable(
+                                std::fs::read_link(format!("/proc/{pid}/exe")).ok(),
+                            );
+
+                            connection_manager.add_connection(connection);
+                            publish(Event::ConnectionSeen {
+                                pid,
+                                address: *address,
+                                port,
+                            });
+                            publish(Event::Pending {
+                                pid: Some(pid),
+                                address: *address,
+                            });
+                        }
+                    }
+                }
+
+                // Renew the leases of the addresses still in use.
+                for connection_info in connections.iter() {
+                    if let Some(connection) =
+                        connection_manager.get_connection_mut(connection_info.remote_address())
+                    {
+                        connection.touch();
                     }
                 }
 
@@ -201,77 +863,662 @@ fn track_process(
                     match connection.state() {
                         ConnectionState::Pending { start_time } => {
                             let elapsed = start_time.elapsed();
-                            if elapsed < delay {
+                            if elapsed < delay || config.vpn_down.load(Ordering::SeqCst) {
+                                continue;
+                            }
+
+                            // Only the process that found the address, or one trying it now,
+                            // escapes it. Migrated addresses wait for such a process.
+                            let address = *connection.address();
+                            let current_port = connections
+                                .iter()
+                                .find(|connection| {
+                                    connection.status() == &TcpConnectionStatus::SynSent
+                                        && connection.remote_address() == &address
+                                })
+                                .map(|connection| connection.remote_port());
+                            if current_port.is_none() && connection.pid() != Some(pid) {
                                 continue;
                             }
 
-                            add_ip_to_routing_table(connection.address(), gateway);
-                            log::info!("Address {} added to routing table.", connection.address());
+                            // Probe the port the process is trying right now, which may be
+                            // the only one open.
+                            let port =
+                                current_port.or_else(|| connection.remote_ports().first().copied());
 
-                            connection.set_state(ConnectionState::InRoutingTable);
+                            // Probes can take a while, run them once the connection manager
+                            // is unlocked.
+                            if let (Some(_), Some(port)) = (config.probe_timeout, port) {
+                                probes.push(SocketAddr::new(address, port));
+
+                                continue;
+                            }
+
+                            escape_connection(connection, config);
                         }
-                        ConnectionState::InRoutingTable => { /* Do nothing. */ }
+                        ConnectionState::InRoutingTable { start_time } => {
+                            let Some(verify_window) = config.verify_window else {
+                                continue;
+                            };
+
+                            // Any tracked process connected to the address verifies the route,
+                            // only the one that needed it or one still trying can roll it back.
+                            let address = *connection.address();
+                            let has_socket = |status| {
+                                connections.iter().any(|connection| {
+                                    connection.status() == &status
+                                        && connection.remote_address() == &address
+                                })
+                            };
+                            let trying = connection.pid() == Some(pid)
+                                || has_socket(TcpConnectionStatus::SynSent);
+                            if has_socket(TcpConnectionStatus::Established) {
+                                log::info!("Address {address} verified.");
+
+                                connection.set_state(ConnectionState::Verified);
+                            } else if trying && start_time.elapsed() >= verify_window {
+                                remove_ip_from_routing_table(&address, config);
+                                log::warn!(
+                                    "No connection established to {address}, removed from routing table."
+                                );
+                                publish(Event::RouteFailed {
+                                    pid: Some(pid),
+                                    address,
+                                    reason: "no connection established".to_owned(),
+                                });
+
+                                connection.set_state(ConnectionState::Failed {
+                                    start_time: Instant::now(),
+                                });
+                            }
+                        }
+                        ConnectionState::Verified
+                        | ConnectionState::Failed { .. }
+                        | ConnectionState::Suspended => { /* Do nothing. */ }
+                    }
+                }
+            }
+
+            // Only escape destinations reachable through the physical interface.
+            for address in probes {
+                // Don't keep probing for a process that was detached meanwhile.
+                match exit_receiver.try_recv() {
+                    Ok(_) => break 'tracking,
+                    Err(TryRecvError::Disconnected) => break 'tracking,
+                    Err(TryRecvError::Empty) => { /* Do nothing. */ }
+                }
+
+                let device = get_gateway(&address.ip(), config).and_then(|gateway| gateway.device);
+                let timeout = config.probe_timeout.unwrap_or_default();
+                let result = probe_reachability(address, device.as_deref(), timeout);
+
+                let connection_manager = get_connection_mananger();
+                let Ok(mut connection_manager) = connection_manager.lock() else {
+                    log::error!("Fail to lock connection manager.");
+
+                    return;
+                };
+                let Some(connection) = connection_manager.get_connection_mut(&address.ip()) else {
+                    continue;
+                };
+                let ConnectionState::Pending { .. } = connection.state() else {
+                    continue;
+                };
+
+                match result {
+                    Ok(_) => {
+                        log::info!(
+                            "Address {address} is reachable through the physical interface."
+                        );
+                        connection.set_probe(ProbeResult::Reachable);
+
+                        escape_connection(connection, config);
+                    }
+                    Err(e) => {
+                        // Only warn once while the address keeps failing the same way.
+                        let probe = ProbeResult::Unreachable(e.to_string());
+                        if connection.probe() != Some(&probe) {
+                            log::warn!(
+                                "Address {address} is unreachable through the physical interface: {e}"
+                            );
+                            publish(Event::RouteFailed {
+                                pid: connection.pid(),
+                                address: address.ip(),
+                                reason: format!("unreachable through the physical interface: {e}"),
+                            });
+                        }
+                        connection.set_probe(probe);
+
+                        // Try again after another delay.
+                        connection.set_state(ConnectionState::Pending {
+                            start_time: Instant::now(),
+                        });
                     }
                 }
             }
         }
 
-        std::thread::sleep(pooling_rate);
+        std::thread::sleep(config.pooling_rate);
+    }
+
+    // Replaced by another attachment before the first check, which tracks the process instead.
+    if !response_sent {
+        send_attach_response(AttachError::Ok, &stream);
+    }
+}
+
+/// Time to wait before retrying an address whose route failed, doubled on every failure in a row.
+fn get_retry_delay(failures: u32) -> Duration {
+    let delay = FAILED_RETRY_DELAY.saturating_mul(1 << failures.saturating_sub(1).min(16));
+
+    delay.min(FAILED_MAX_RETRY_DELAY)
+}
+
+/// Adds a pending connection to the routing table.
+fn escape_connection(connection: &mut Connection, config: &ServiceConfig) {
+    if !add_to_routing_table(connection, config) {
+        // Try again after another delay.
+        connection.set_state(ConnectionState::Pending {
+            start_time: Instant::now(),
+        });
+
+        return;
     }
+    log::info!("Address {} added to routing table.", connection.address());
+
+    connection.set_state(ConnectionState::InRoutingTable {
+        start_time: Instant::now(),
+    });
 }
 
-fn get_ipv4_pending_connections_from_pid(pid: u32) -> Result<Vec<Ipv4Addr>> {
-    let connections = get_ipv4_connection_info_from_pid(pid)?;
+/// Reads the TCP sockets of the network namespace of the first process, keeping only the ones
+/// owned by the given processes.
+fn get_connection_info_from_pids(
+    source: &dyn ConnectionSource,
+    pids: &[u32],
+    states: &[TcpConnectionStatus],
+) -> Result<Vec<TcpConnectionInfo>> {
+    let pid = pids[0];
 
-    let connections = connections
+    // Descendants can exit at any time, only the first process is required to exist.
+    let mut inodes = get_socket_inodes(pid)?;
+    for child in &pids[1..] {
+        inodes.extend(get_socket_inodes(*child).unwrap_or_default());
+    }
+
+    let mut connections: Vec<TcpConnectionInfo> = source
+        .get_connections(pid, states)?
         .into_iter()
-        .filter_map(|connection| {
-            if connection.status() == &TcpConnectionStatus::SynSent {
-                Some(connection.remote_address().to_owned())
-            } else {
-                None
-            }
-        })
+        .filter(|connection| inodes.contains(&connection.inode()))
         .collect();
 
+    connections.dedup();
+
     Ok(connections)
 }
 
-fn get_ipv4_connection_info_from_pid(pid: u32) -> Result<Vec<TcpConnectionInfo>> {
-    let tcp_file = std::fs::read_to_string(format!("/proc/{}/net/tcp", pid))?;
+fn monitor_network(config: &ServiceConfig) {
+    // Follow the default route when the gateway is detected automatically.
+    let auto_gateway = matches!(config.gateway, GatewaySetting::Auto)
+        || matches!(config.gateway6, Some(GatewaySetting::Auto));
+
+    let mut vpn_up = None;
+    loop {
+        std::thread::sleep(config.pooling_rate);
+
+        if auto_gateway {
+            update_gateways(config);
+        }
+        update_vpn_state(config, &mut vpn_up);
+        if let Some(lease) = config.lease {
+            expire_connections(lease, config);
+        }
+
+        save_connections();
+    }
+}
+
+/// Saves the changes made to the connections by every thread.
+fn save_connections() {
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
 
-    let mut connections: Vec<TcpConnectionInfo> = tcp_file
-        .lines()
-        .skip(1)
-        .map(|line| line.try_into().unwrap())
+        return;
+    };
+
+    connection_manager.flush();
+}
+
+/// Removes the addresses that no tracked process used during the lease, except pinned ones.
+fn expire_connections(lease: Duration, config: &ServiceConfig) {
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
+
+        return;
+    };
+
+    let expired: Vec<_> = connection_manager
+        .iter()
+        .filter(|connection| {
+            !config.pinned.contains(connection.address())
+                && connection.last_seen().elapsed().unwrap_or_default() >= lease
+        })
+        .map(|connection| {
+            (
+                *connection.address(),
+                connection.state().is_in_routing_table(),
+            )
+        })
         .collect();
+    for (address, in_routing_table) in expired {
+        if in_routing_table {
+            remove_ip_from_routing_table(&address, config);
+        }
+        connection_manager.remove_connection(&address);
 
-    connections.dedup();
+        log::info!("Address {address} expired.");
+    }
+}
 
-    Ok(connections)
+/// Removes the addresses that the process added, unless another attached process still connects
+/// to them.
+fn drop_process_routes(pid: u32, config: &ServiceConfig) {
+    let processes = match list_processes() {
+        Ok(processes) => processes,
+        Err(e) => {
+            log::error!("Fail to list processes: {e}");
+
+            return;
+        }
+    };
+
+    let mut in_use = HashSet::new();
+    for info in processes {
+        // Skip the processes whose PID was reused, their tracking stops soon.
+        if get_process_start_time(info.pid).ok() != Some(info.start_time) {
+            continue;
+        }
+
+        let pids = if info.children {
+            get_process_tree(info.pid)
+        } else {
+            Ok(vec![info.pid])
+        };
+        let states = [
+            TcpConnectionStatus::SynSent,
+            TcpConnectionStatus::Established,
+        ];
+        let connections = pids.and_then(|pids| {
+            get_connection_info_from_pids(config.connection_source.as_ref(), &pids, &states)
+        });
+
+        // Processes that exited meanwhile don't need anything.
+        for connection in connections.unwrap_or_default() {
+            in_use.insert(*connection.remote_address());
+        }
+    }
+
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
+
+        return;
+    };
+
+    let unused: Vec<_> = connection_manager
+        .iter()
+        .filter(|connection| {
+            connection.pid() == Some(pid)
+                && !config.pinned.contains(connection.address())
+                && !in_use.contains(connection.address())
+        })
+        .map(|connection| {
+            (
+                *connection.address(),
+                connection.state().is_in_routing_table(),
+            )
+        })
+        .collect();
+    for (address, in_routing_table) in unused {
+        if in_routing_table {
+            remove_ip_from_routing_table(&address, config);
+        }
+        connection_manager.remove_connection(&address);
+
+        log::info!("Address {address} no longer needed after PID: {pid}");
+    }
+}
+
+/// Suspends the routes in the routing table when the VPN goes down and restores them once it is
+/// back up.
+///
+/// Nothing is suspended until a VPN interface has been seen up at least once.
+fn update_vpn_state(config: &ServiceConfig, vpn_up: &mut Option<bool>) {
+    let interfaces = match config.vpn_interfaces.find_up() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            log::error!("Fail to find VPN interfaces: {e}");
+
+            return;
+        }
+    };
+
+    let up = !interfaces.is_empty();
+    let was_up = vpn_up.replace(up);
+    if was_up == Some(up) {
+        return;
+    }
+
+    if !up && was_up.is_none() {
+        log::info!("No VPN interface found, routes won't be suspended until one is up.");
+
+        return;
+    }
+    if up {
+        log::info!("VPN is up: {}", interfaces.join(", "));
+        update_vpn_layout(config);
+    } else {
+        log::info!("VPN is down.");
+    }
+    config.vpn_down.store(!up, Ordering::SeqCst);
+
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
+
+        return;
+    };
+
+    for connection in connection_manager.iter_mut() {
+        let address = *connection.address();
+
+        match (connection.state(), up) {
+            (state, false) if state.is_in_routing_table() => {
+                remove_ip_from_routing_table(&address, config);
+                log::info!("Address {address} suspended.");
+
+                connection.set_state(ConnectionState::Suspended);
+            }
+            (ConnectionState::Suspended, true) => {
+                if !add_to_routing_table(connection, config) {
+                    connection.set_state(ConnectionState::Pending {
+                        start_time: Instant::now(),
+                    });
+
+                    continue;
+                }
+                log::info!("Address {address} restored to routing table.");
+
+                connection.set_state(ConnectionState::InRoutingTable {
+                    start_time: Instant::now(),
+                });
+            }
+
+            _ => { /* Do nothing. */ }
+        }
+    }
 }
 
-fn add_ip_to_routing_table(ip: &Ipv4Addr, gateway: &str) {
-    Command::new("ip")
-        .args(["route", "add", &format!("{}/32", ip), "via", gateway])
-        .spawn()
-        .unwrap();
+/// Moves the escape routes where they take precedence over the routes of the VPN that came up.
+fn update_vpn_layout(config: &ServiceConfig) {
+    let vpn_layout = match VpnLayout::detect(config.route_backend_kind) {
+        Ok(vpn_layout) => vpn_layout,
+        Err(e) => {
+            log::error!("Fail to detect VPN routing layout: {e}");
+
+            return;
+        }
+    };
+
+    let placement = RoutePlacement::select(
+        &vpn_layout,
+        config.requested_placement.table,
+        config.requested_placement.rule_priority,
+    );
+    let backend = match create_route_backend(
+        config.route_backend_kind,
+        placement.table.unwrap_or(MAIN_TABLE),
+    ) {
+        Ok(backend) => backend,
+        Err(e) => {
+            log::error!("Fail to create route backend: {e}");
+
+            return;
+        }
+    };
+
+    // Release the route target before locking the connection manager.
+    let old_target = {
+        let Ok(mut route_target) = config.route_target.write() else {
+            log::error!("Fail to lock route target.");
+
+            return;
+        };
+        if route_target.placement == placement {
+            log::info!("Detected {vpn_layout}, escape routes stay in {placement}.");
+
+            return;
+        }
+        log::info!("Detected {vpn_layout}, moving escape routes to {placement}.");
+
+        let new_target = RouteTarget { backend, placement };
+        new_target.add_rule();
+
+        std::mem::replace(&mut *route_target, new_target)
+    };
+
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
+
+        return;
+    };
+
+    let mut moved = 0;
+    for connection in connection_manager.iter_mut() {
+        let address = *connection.address();
+        if !connection.state().is_in_routing_table() {
+            continue;
+        }
+
+        match old_target.backend.remove_route(&address) {
+            Ok(_) | Err(RouteError::NotFound) => { /* Do nothing. */ }
+            Err(e) => log::error!("Fail to remove {address} from routing table: {e}"),
+        }
+        if !add_to_routing_table(connection, config) {
+            connection.set_state(ConnectionState::Pending {
+                start_time: Instant::now(),
+            });
+
+            continue;
+        }
+
+        moved += 1;
+    }
+    old_target.remove_rule();
+
+    log::info!("Moved {moved} addresses to {placement}.");
 }
 
-fn remove_ip_from_routing_table(ip: &Ipv4Addr) {
-    let ip = ip.to_string();
+/// Finds the gateways again, moving every route in the routing table to the new gateway when it
+/// changes.
+fn update_gateways(config: &ServiceConfig) {
+    let new_gateways = match Gateways::resolve(
+        &config.gateway,
+        config.gateway6.as_ref(),
+        &config.vpn_interfaces,
+    ) {
+        Ok(gateways) => gateways,
+        Err(e) => {
+            log::error!("Fail to find gateways: {e}");
+
+            return;
+        }
+    };
 
-    Command::new("ip")
-        .args(["route", "del", &ip])
-        .spawn()
-        .expect("Fail to remove IP from routing table.");
+    // Release the gateways before locking the connection manager.
+    let old_gateways = {
+        let Ok(mut gateways) = config.gateways.write() else {
+            log::error!("Fail to lock gateways.");
+
+            return;
+        };
+        if *gateways == new_gateways {
+            return;
+        }
+
+        std::mem::replace(&mut *gateways, new_gateways.clone())
+    };
+
+    let connection_manager = get_connection_mananger();
+    let Ok(mut connection_manager) = connection_manager.lock() else {
+        log::error!("Fail to lock connection manager.");
+
+        return;
+    };
+
+    for (old_gateway, new_gateway) in [
+        (&old_gateways.ipv4, &new_gateways.ipv4),
+        (&old_gateways.ipv6, &new_gateways.ipv6),
+    ] {
+        if old_gateway == new_gateway {
+            continue;
+        }
+
+        let Some(new_gateway) = new_gateway else {
+            log::warn!(
+                "Gateway {} is gone, waiting for a new default route...",
+                display_gateway(old_gateway)
+            );
+
+            continue;
+        };
+        log::info!(
+            "Gateway changed from {} to {new_gateway}.",
+            display_gateway(old_gateway)
+        );
+
+        let mut migrated = 0;
+        for connection in connection_manager.iter_mut() {
+            let address = *connection.address();
+            if !connection.state().is_in_routing_table() {
+                continue;
+            }
+            if address.is_ipv4() != new_gateway.address.is_ipv4() {
+                continue;
+            }
+
+            remove_ip_from_routing_table(&address, config);
+            if !add_to_routing_table(connection, config) {
+                connection.set_state(ConnectionState::Pending {
+                    start_time: Instant::now(),
+                });
+
+                continue;
+            }
+
+            log::info!("Address {address} migrated to gateway {new_gateway}.");
+            migrated += 1;
+        }
+
+        log::info!("Migrated {migrated} addresses to gateway {new_gateway}.");
+    }
+}
+
+fn display_gateway(gateway: &Option<Gateway>) -> String {
+    match gateway {
+        Some(gateway) => gateway.to_string(),
+        None => "none".to_owned(),
+    }
+}
+
+fn get_gateway(ip: &IpAddr, config: &ServiceConfig) -> Option<Gateway> {
+    let Ok(gateways) = config.gateways.read() else {
+        log::error!("Fail to lock gateways.");
+
+        return None;
+    };
+
+    gateways.get(ip).cloned()
+}
+
+/// Adds the address of the connection to the routing table, returning whether the route is in
+/// place.
+fn add_to_routing_table(connection: &mut Connection, config: &ServiceConfig) -> bool {
+    let ip = connection.address();
+    let Some(gateway) = get_gateway(ip, config) else {
+        log::warn!("No gateway configured to escape: {ip}");
+
+        return false;
+    };
+
+    let Ok(route_target) = config.route_target.read() else {
+        log::error!("Fail to lock route target.");
+
+        return false;
+    };
+
+    match route_target.backend.add_route(ip, &gateway) {
+        Ok(_) => { /* Do nothing. */ }
+        Err(RouteError::AlreadyExists) => log::warn!("Route to {ip} already exists."),
+
+        Err(e) => {
+            log::error!("Fail to add {ip} to routing table: {e}");
+            publish(Event::RouteFailed {
+                pid: connection.pid(),
+                address: *ip,
+                reason: e.to_string(),
+            });
+
+            return false;
+        }
+    }
+    publish(Event::RouteAdded {
+        pid: connection.pid(),
+        address: *ip,
+        gateway: gateway.address,
+    });
+    connection.set_gateway(Some(gateway.address));
+
+    true
+}
+
+fn remove_ip_from_routing_table(ip: &IpAddr, config: &ServiceConfig) {
+    let Ok(route_target) = config.route_target.read() else {
+        log::error!("Fail to lock route target.");
+
+        return;
+    };
+
+    match route_target.backend.remove_route(ip) {
+        Ok(_) | Err(RouteError::NotFound) => { /* Do nothing. */ }
+        Err(e) => log::error!("Fail to remove {ip} from routing table: {e}"),
+    }
+}
+
+fn send_attach_response(error: AttachError, stream: &UnixStream) {
+    send_response(&Message::AttachResponse { error }, stream);
+}
+
+fn send_detach_response(error: DetachError, stream: &UnixStream) {
+    send_response(&Message::DetachResponse { error }, stream);
+}
+
+fn send_purge_response(error: PurgeError, stream: &UnixStream) {
+    let msg = Message::PurgeResponse {
+        error,
+        addresses: Vec::new(),
+    };
+    send_response(&msg, stream);
 }
 
-fn send_attach_response(error: AttachError, stream: &TcpStream) {
-    match serialize_to(&Message::AttachResponse { error }, stream) {
+fn send_response(msg: &Message, stream: &UnixStream) {
+    match serialize_to(msg, stream) {
         Ok(_) => { /* Do nothing. */ }
         Err(e) => {
-            log::error!("Fail to send response to service: {e}");
+            log::error!("Fail to send response to client: {e}");
         }
     }
 }
@@ -0,0 +1,84 @@
+use color_eyre::eyre::{eyre, Result};
+use std::{
+    fs::DirBuilder,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// State directory of a service running as root.
+const SYSTEM_STATE_DIR: &str = "/var/lib/escape-vpn";
+
+/// Returns the directory holding the socket and state files.
+pub fn get_state_dir() -> &'static Path {
+    STATE_DIR.get_or_init(get_user_state_dir)
+}
+
+pub fn set_state_dir(path: PathBuf) {
+    if STATE_DIR.set(path).is_err() {
+        log::warn!("State directory already set.");
+    }
+}
+
+/// State directory used by the service when none is given.
+pub fn get_default_state_dir() -> PathBuf {
+    if get_effective_uid() == 0 {
+        PathBuf::from(SYSTEM_STATE_DIR)
+    } else {
+        get_user_state_dir()
+    }
+}
+
+/// Finds the state directory of a running service, looking for its socket in the directory of the
+/// current user first.
+pub fn find_state_dir(socket_file_name: &str) -> PathBuf {
+    [get_user_state_dir(), PathBuf::from(SYSTEM_STATE_DIR)]
+        .into_iter()
+        .find(|path| path.join(socket_file_name).exists())
+        .unwrap_or_else(get_default_state_dir)
+}
+
+/// Creates the state directory and makes sure no other user can tamper with it.
+pub fn prepare_state_dir(path: &Path) -> Result<()> {
+    DirBuilder::new().recursive(true).mode(0o755).create(path)?;
+
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_dir() {
+        return Err(eyre!("{} isn't a directory", path.display()));
+    }
+    if metadata.uid() != get_effective_uid() {
+        return Err(eyre!("{} is owned by another user", path.display()));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(eyre!("{} is writable by other users", path.display()));
+    }
+
+    Ok(())
+}
+
+/// Makes sure the file was created by root or the current user, not by someone impersonating
+/// the service.
+pub fn check_file_owner(path: &Path) -> Result<()> {
+    let metadata = std::fs::metadata(path)?;
+    if metadata.uid() != 0 && metadata.uid() != get_effective_uid() {
+        return Err(eyre!("{} is owned by another user", path.display()));
+    }
+
+    Ok(())
+}
+
+/// `$XDG_RUNTIME_DIR/escape-vpn`, or a directory named after the user in the temporary directory.
+fn get_user_state_dir() -> PathBuf {
+    let exe_name = env!("CARGO_PKG_NAME");
+
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join(exe_name),
+        None => std::env::temp_dir().join(format!("{exe_name}-{}", get_effective_uid())),
+    }
+}
+
+fn get_effective_uid() -> u32 {
+    unsafe { libc::geteuid() }
+}